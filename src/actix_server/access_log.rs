@@ -0,0 +1,77 @@
+use std::io::Write;
+use std::sync::Mutex;
+use actix_web::http::header::{HeaderName, REFERER, USER_AGENT};
+use chrono::Local;
+use crate::errors::HttpResult;
+use super::{error_status, Middleware, Next, Request, Response};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    Common,
+    Combined,
+}
+
+/// Writes one Apache Common or Combined Log Format line per request. Lines go to `log::info!`
+/// unless a writer is set.
+pub struct AccessLogMiddleware {
+    format: AccessLogFormat,
+    writer: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl AccessLogMiddleware {
+    pub fn new(format: AccessLogFormat) -> Self {
+        Self {
+            format,
+            writer: None,
+        }
+    }
+
+    pub fn set_writer(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.writer = Some(Mutex::new(Box::new(writer)));
+        self
+    }
+
+    fn write_line(&self, line: String) {
+        match self.writer.as_ref() {
+            Some(writer) => {
+                let mut writer = writer.lock().unwrap();
+                if let Err(e) = writeln!(writer, "{}", line) {
+                    log::error!("write access log failed.{}", e);
+                }
+            }
+            None => log::info!("{}", line),
+        }
+    }
+}
+
+fn header_or_dash<State>(req: &Request<State>, name: HeaderName) -> String {
+    req.header(name).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string()
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for AccessLogMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let remote = req.request().peer_addr().map(|addr| addr.ip().to_string()).unwrap_or("-".to_string());
+        let timestamp = Local::now().format("%d/%b/%Y:%H:%M:%S %z");
+        let request_line = format!("{} {} {:?}",
+                                   req.method(),
+                                   req.request().uri().path_and_query().map(|v| v.as_str()).unwrap_or("/"),
+                                   req.http_version());
+        let referer = header_or_dash(&req, REFERER);
+        let user_agent = header_or_dash(&req, USER_AGENT);
+
+        let ret = next.run(req).await;
+        let (status, bytes) = match ret.as_ref() {
+            Ok(resp) => (resp.status(), resp.len().unwrap_or(0)),
+            Err(e) => (error_status(e.code()), 0),
+        };
+        let bytes = if bytes == 0 { "-".to_string() } else { bytes.to_string() };
+
+        let mut line = format!("{} - - [{}] \"{}\" {} {}", remote, timestamp, request_line, status.as_u16(), bytes);
+        if self.format == AccessLogFormat::Combined {
+            line = format!("{} \"{}\" \"{}\"", line, referer, user_agent);
+        }
+        self.write_line(line);
+        ret
+    }
+}
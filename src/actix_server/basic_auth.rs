@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use actix_web::http::header::{AUTHORIZATION, HeaderValue, WWW_AUTHENTICATE};
+use actix_web::http::StatusCode;
+use sha2::Digest;
+use crate::errors::{ErrorCode, HttpResult, into_http_err};
+use super::{Middleware, Next, Request, Response};
+
+fn password_digest(password: &str) -> [u8; 32] {
+    sha2::Sha256::digest(password.as_bytes()).into()
+}
+
+fn constant_time_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    let diff = a.iter().zip(b.iter()).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    std::hint::black_box(diff) == 0
+}
+
+type CredentialChecker = Arc<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// Checks `Authorization: Basic` credentials and answers 401 with a `WWW-Authenticate`
+/// challenge for `realm` when they are missing or rejected.
+pub struct BasicAuthMiddleware {
+    realm: String,
+    checker: CredentialChecker,
+}
+
+impl BasicAuthMiddleware {
+    pub fn new(realm: impl Into<String>, checker: impl Fn(&str, &str) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            realm: realm.into(),
+            checker: Arc::new(checker),
+        }
+    }
+
+    /// Passwords are compared in constant time, by their SHA-256 digests.
+    pub fn from_map(realm: impl Into<String>, users: HashMap<String, String>) -> Self {
+        let users: HashMap<String, [u8; 32]> = users.into_iter().map(|(user, password)| (user, password_digest(password.as_str()))).collect();
+        Self::new(realm, move |user, password| {
+            let digest = password_digest(password);
+            match users.get(user) {
+                Some(expected) => constant_time_eq(expected, &digest),
+                None => {
+                    // compare anyway, so unknown users take as long as wrong passwords
+                    constant_time_eq(&[0; 32], &digest);
+                    false
+                }
+            }
+        })
+    }
+
+    fn unauthorized(&self) -> HttpResult<Response> {
+        let challenge = HeaderValue::from_str(format!("Basic realm=\"{}\"", self.realm).as_str())
+            .map_err(into_http_err!(ErrorCode::InvalidParam, "invalid realm {}", self.realm))?;
+        Ok(Response::builder(StatusCode::UNAUTHORIZED).header(WWW_AUTHENTICATE, challenge).build())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for BasicAuthMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let authorization = req.header(AUTHORIZATION).and_then(|v| v.to_str().ok());
+        match crate::header_util::basic_credentials(authorization) {
+            Some((user, password)) if (self.checker)(user.as_str(), password.as_str()) => next.run(req).await,
+            Some((user, _)) => {
+                log::warn!("basic auth failed for user {}", user);
+                self.unauthorized()
+            }
+            None => self.unauthorized(),
+        }
+    }
+}
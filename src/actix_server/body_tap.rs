@@ -0,0 +1,103 @@
+use actix_web::body::{BodySize, BoxBody, MessageBody};
+use crate::errors::{ErrorCode, http_err, HttpResult};
+use super::{Middleware, Next, Request, Response};
+
+/// Logs request and response bodies at trace level for debugging integrations. Logged bodies
+/// are capped at `max_log_size` bytes and values of the redacted json fields are masked.
+/// Streaming responses are passed through without being logged.
+pub struct BodyTapMiddleware {
+    max_log_size: usize,
+    redact_fields: Vec<String>,
+}
+
+impl Default for BodyTapMiddleware {
+    fn default() -> Self {
+        Self {
+            max_log_size: 4096,
+            redact_fields: vec!["password".to_string(), "token".to_string()],
+        }
+    }
+}
+
+impl BodyTapMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_max_log_size(mut self, max_log_size: usize) -> Self {
+        self.max_log_size = max_log_size;
+        self
+    }
+
+    pub fn set_redact_fields(mut self, redact_fields: Vec<String>) -> Self {
+        self.redact_fields = redact_fields;
+        self
+    }
+
+    fn redact(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if self.redact_fields.iter().any(|v| v.eq_ignore_ascii_case(key)) {
+                        *value = serde_json::Value::String("***".to_string());
+                    } else {
+                        self.redact(value);
+                    }
+                }
+            }
+            serde_json::Value::Array(list) => {
+                for value in list.iter_mut() {
+                    self.redact(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn format_body(&self, body: &[u8]) -> String {
+        let mut text = match serde_json::from_slice::<serde_json::Value>(body) {
+            Ok(mut value) => {
+                self.redact(&mut value);
+                value.to_string()
+            }
+            Err(_) => String::from_utf8_lossy(body).to_string(),
+        };
+        if text.len() > self.max_log_size {
+            let mut end = self.max_log_size;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+            text.push_str("...");
+        }
+        text
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for BodyTapMiddleware {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let path = req.request().path().to_string();
+        if log::log_enabled!(log::Level::Trace) {
+            let body = req.body_bytes().await?;
+            log::trace!("request {} {} body {}", req.method(), path, self.format_body(body.as_slice()));
+            req.set_body(body);
+        }
+
+        let mut resp = next.run(req).await?;
+        if log::log_enabled!(log::Level::Trace) {
+            let http_resp = resp.resp.take().unwrap();
+            if let BodySize::Sized(_) = http_resp.body().size() {
+                let (http_resp, body) = http_resp.into_parts();
+                let body = actix_web::body::to_bytes(body).await
+                    .map_err(|e| http_err!(ErrorCode::ServerError, "read response body failed {}", e))?;
+                log::trace!("response {} {} body {}", path, http_resp.status(), self.format_body(&body));
+                resp.resp = Some(http_resp.set_body(BoxBody::new(body)));
+            } else {
+                log::trace!("response {} {} body <stream>", path, http_resp.status());
+                resp.resp = Some(http_resp);
+            }
+        }
+        Ok(resp)
+    }
+}
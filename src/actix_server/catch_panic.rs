@@ -0,0 +1,33 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use futures_util::FutureExt;
+use crate::errors::{ErrorCode, HttpError, HttpResult};
+use super::{Middleware, Next, Request, Response};
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.as_str()
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Turns a panic in the wrapped endpoints into a `ServerError` response instead of dropping
+/// the connection without an answer.
+pub struct CatchPanicMiddleware;
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for CatchPanicMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let path = req.request().path().to_string();
+        match AssertUnwindSafe(next.run(req)).catch_unwind().await {
+            Ok(ret) => ret,
+            Err(panic) => {
+                log::error!("request {} panicked: {}", path, panic_message(panic.as_ref()));
+                Err(HttpError::new(ErrorCode::ServerError, "internal server error".to_string()))
+            }
+        }
+    }
+}
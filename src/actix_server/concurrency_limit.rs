@@ -0,0 +1,54 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use actix_web::http::StatusCode;
+use crate::errors::HttpResult;
+use super::{Middleware, Next, Request, Response};
+
+struct Permit(Arc<AtomicUsize>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Bounds the number of requests running through the wrapped endpoints at the same time and
+/// answers 503 once `max` are in flight. Each instance keeps its own count, so wrapping
+/// a single route limits only that route.
+pub struct ConcurrencyLimitMiddleware {
+    max: usize,
+    running: Arc<AtomicUsize>,
+}
+
+impl ConcurrencyLimitMiddleware {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn try_acquire(&self) -> Option<Permit> {
+        self.running.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+            if running < self.max {
+                Some(running + 1)
+            } else {
+                None
+            }
+        }).ok().map(|_| Permit(self.running.clone()))
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ConcurrencyLimitMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let _permit = match self.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                log::warn!("request {} rejected, {} requests running", req.request().path(), self.max);
+                return Ok(Response::new(StatusCode::SERVICE_UNAVAILABLE));
+            }
+        };
+        next.run(req).await
+    }
+}
@@ -0,0 +1,42 @@
+use actix_web::http::header::TRANSFER_ENCODING;
+use crate::errors::{ErrorCode, HttpError, HttpResult};
+use super::{Middleware, Next, Request, Response};
+
+pub(crate) fn has_body<State>(req: &Request<State>) -> bool {
+    let chunked = req.header(TRANSFER_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_ascii_lowercase().contains("chunked"))
+        .unwrap_or(false);
+    chunked || req.content_length().map(|v| v > 0).unwrap_or(false)
+}
+
+/// Answers 415 to requests with a body whose `Content-Type`, parameters ignored, is not one
+/// of the allowed media types, or missing. Requests without a body pass through.
+pub struct ContentTypeMiddleware {
+    allowed: Vec<String>,
+}
+
+impl ContentTypeMiddleware {
+    pub fn new<I, S>(allowed: I) -> Self
+        where
+            I: IntoIterator<Item = S>,
+            S: Into<String>, {
+        Self {
+            allowed: allowed.into_iter().map(|v| v.into()).collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ContentTypeMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        if !has_body(&req) {
+            return next.run(req).await;
+        }
+        let content_type = req.content_type();
+        if !self.allowed.iter().any(|v| v.eq_ignore_ascii_case(content_type)) {
+            return Err(HttpError::new(ErrorCode::UnsupportedMediaType, format!("unsupported content type '{}'", content_type)));
+        }
+        next.run(req).await
+    }
+}
@@ -1,28 +1,164 @@
-use std::ffi::OsStr;
+use std::borrow::Cow;
 use std::future::Future;
+use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
 use actix_files::NamedFile;
-use actix_web::{FromRequest, Handler, HttpMessage, HttpRequest, HttpResponse, Responder, web};
+use actix_web::{FromRequest, Handler, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder, Responder, web};
 use actix_web::body::BoxBody;
+use actix_web::cookie::Cookie;
 use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Url};
-use actix_web::http::{Method, StatusCode, Version};
-use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode, Uri, Version};
+use actix_web::http::header::{ACCEPT, ACCESS_CONTROL_REQUEST_HEADERS, ALLOW, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, FORWARDED, HeaderName, HeaderValue, HttpDate, IF_MODIFIED_SINCE, IF_NONE_MATCH, ORIGIN, VARY};
 use async_trait::async_trait;
 use futures_util::future::LocalBoxFuture;
 use futures_util::stream::IntoAsyncRead;
-use futures_util::{AsyncReadExt, StreamExt, TryStreamExt};
+use futures_util::{AsyncReadExt, Stream, StreamExt, TryStreamExt};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 use crate::actix_server::body::{BodySize, MessageBody};
-use crate::errors::{ErrorCode, http_err, HttpError, HttpResult, into_http_err};
+use crate::cors::CorsConfig;
+use crate::errors::{ErrorCode, http_err, HttpError, HttpResult, into_http_err, ResponseCode, with_error_detail};
+use super::HttpJsonResult;
+
+const BODY_PREALLOCATE_LIMIT: usize = 1024 * 1024;
+pub const DEFAULT_JSON_LIMIT: usize = 16 * 1024 * 1024;
+pub const DEFAULT_DECOMPRESS_LIMIT: usize = 64 * 1024 * 1024;
+
+/// How endpoint errors are written to the response body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// The `{err, msg, result}` json envelope of `HttpJsonResult`.
+    Envelope,
+    /// RFC 7807 `application/problem+json`, with a `urn:sfo-http:error:<code>` type.
+    ProblemJson,
+}
+
+fn problem_type(code: ErrorCode) -> String {
+    let name = match code {
+        ErrorCode::Failed => "failed".to_string(),
+        ErrorCode::InvalidData => "invalid-data".to_string(),
+        ErrorCode::ConnectFailed => "connect-failed".to_string(),
+        ErrorCode::InvalidParam => "invalid-param".to_string(),
+        ErrorCode::ServerError => "server-error".to_string(),
+        ErrorCode::NotFound => "not-found".to_string(),
+        ErrorCode::IOError => "io-error".to_string(),
+        ErrorCode::Timeout => "timeout".to_string(),
+        ErrorCode::PayloadTooLarge => "payload-too-large".to_string(),
+        ErrorCode::UnsupportedMediaType => "unsupported-media-type".to_string(),
+        ErrorCode::HttpStatus(status) => format!("http-{}", status),
+        ErrorCode::Custom(code, _) => format!("custom-{}", code),
+    };
+    format!("urn:sfo-http:error:{}", name)
+}
+
+pub(crate) type ErrorHandler = Arc<dyn Fn(&HttpError) -> Response + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct EndpointConfig {
+    pub(crate) max_body_size: Option<usize>,
+    pub(crate) expose_error_detail: bool,
+    pub(crate) request_timeout: Option<Duration>,
+    /// Decode gzip and deflate request bodies, on by default. Bodies with other codings are
+    /// passed through as sent.
+    pub(crate) decompress_request: bool,
+    pub(crate) error_handlers: Vec<(ErrorCode, ErrorHandler)>,
+    pub(crate) error_format: ErrorFormat,
+}
+
+impl Default for EndpointConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: None,
+            expose_error_detail: true,
+            request_timeout: None,
+            decompress_request: true,
+            error_handlers: Vec::new(),
+            error_format: ErrorFormat::Envelope,
+        }
+    }
+}
+
+// runs blocking file io on the blocking thread pool, keeping the worker free for other requests
+async fn block_io<R: Send + 'static>(f: impl FnOnce() -> std::io::Result<R> + Send + 'static) -> std::io::Result<R> {
+    web::block(f).await.map_err(std::io::Error::other)?
+}
+
+pub(crate) fn error_status(code: impl ResponseCode) -> StatusCode {
+    StatusCode::from_u16(code.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+pub(crate) fn error_response(err: &HttpError, config: &EndpointConfig, path: &str) -> Response {
+    log::error!("endpoint failed {:?}", err);
+    if let Some((_, handler)) = config.error_handlers.iter().find(|(code, _)| *code == err.code()) {
+        return handler(err);
+    }
+    let msg = if config.expose_error_detail && !err.msg().is_empty() {
+        err.msg().to_string()
+    } else {
+        format!("{:?}", err.code())
+    };
+    let status = error_status(err.code());
+    if config.error_format == ErrorFormat::ProblemJson {
+        let problem = serde_json::json!({
+            "type": problem_type(err.code()),
+            "title": status.canonical_reason().unwrap_or("Error"),
+            "status": status.as_u16(),
+            "detail": msg,
+            "instance": path,
+        });
+        return Response::builder(status).content_type("application/problem+json").body(problem.to_string());
+    }
+    let ret = HttpJsonResult::<()> {
+        err: err.code().into(),
+        msg,
+        result: None,
+    };
+    Response::json(status, &ret).unwrap_or_else(|_| Response::new(status))
+}
+
+/// Cancelled when a request is abandoned before its response is produced, because the client
+/// disconnected or the request timed out. Handlers pass it to background work they spawn.
+#[derive(Clone, Default, Debug)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+struct CancelOnDrop(Option<CancellationToken>);
+
+impl CancelOnDrop {
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
 
 pub struct Request<State> {
     state: State,
     request: HttpRequest,
     payload: Option<Payload>,
+    body_decoded: bool,
 }
 
 impl<State> Request<State> {
@@ -42,10 +178,33 @@ impl<State> Request<State> {
         self.request.match_info().get_ref()
     }
 
+    pub fn uri(&self) -> &Uri {
+        self.request.uri()
+    }
+
+    /// The absolute url the client requested, with scheme and host taken from the
+    /// `Forwarded` or `X-Forwarded-*` headers when behind a proxy.
+    pub fn full_url(&self) -> Option<String> {
+        let path = self.request.uri().path_and_query().map(|v| v.as_str()).unwrap_or("/");
+        Some(format!("{}://{}{}", self.scheme()?, self.host()?, path))
+    }
+
     pub fn version(&self) -> Option<Version> {
         Some(self.request.version())
     }
 
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.request.extensions().get::<CancellationToken>().cloned().unwrap_or_default()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation_token().is_cancelled()
+    }
+
+    pub fn http_version(&self) -> Version {
+        self.request.version()
+    }
+
     pub fn peer_addr(&self) -> Option<String> {
         self.request.peer_addr().map(|addr| addr.to_string())
     }
@@ -58,6 +217,38 @@ impl<State> Request<State> {
         self.request.connection_info().realip_remote_addr().map(|addr| addr.to_string())
     }
 
+    pub fn client_ip(&self, trusted_proxies: usize) -> Option<IpAddr> {
+        let x_forwarded_for = self.header_value_list(HeaderName::from_static("x-forwarded-for"));
+        let peer = self.request.peer_addr().map(|addr| addr.ip());
+        crate::header_util::forwarded_client_ip(x_forwarded_for.as_deref(), peer, trusted_proxies)
+    }
+
+    pub fn forwarded(&self) -> Vec<crate::header_util::ForwardedElement> {
+        self.header_value_list(FORWARDED)
+            .map(|v| crate::header_util::parse_forwarded(v.as_str()))
+            .unwrap_or_default()
+    }
+
+    pub fn proto(&self) -> Option<String> {
+        let forwarded = self.header_value_list(FORWARDED);
+        let x_forwarded_proto = self.header_value_list(HeaderName::from_static("x-forwarded-proto"));
+        crate::header_util::forwarded_proto(forwarded.as_deref(), x_forwarded_proto.as_deref())
+    }
+
+    pub fn scheme(&self) -> Option<String> {
+        self.proto().or_else(|| {
+            if self.request.app_config().secure() {
+                Some("https".to_string())
+            } else {
+                Some("http".to_string())
+            }
+        })
+    }
+
+    pub fn is_secure(&self) -> bool {
+        matches!(self.scheme().as_deref(), Some("https") | Some("wss"))
+    }
+
     pub fn host(&self) -> Option<String> {
         Some(self.request.connection_info().host().to_string())
     }
@@ -75,13 +266,85 @@ impl<State> Request<State> {
         self.request.headers().get_all(key.into())
     }
 
+    pub fn accepts(&self, mime: &str) -> bool {
+        let accept = self.header_value_list(ACCEPT);
+        crate::header_util::accepts(accept.as_deref(), mime)
+    }
+
+    pub fn preferred_content_type(&self, offered: &[&str]) -> Option<String> {
+        let accept = self.header_value_list(ACCEPT);
+        crate::header_util::preferred_content_type(accept.as_deref(), offered)
+    }
+
+    pub fn bearer_token(&self) -> Option<String> {
+        let authorization = self.header(AUTHORIZATION).and_then(|v| v.to_str().ok());
+        crate::header_util::bearer_token(authorization)
+    }
+
+    pub fn if_none_match(&self) -> Option<String> {
+        self.header_value_list(IF_NONE_MATCH)
+    }
+
+    pub fn if_modified_since(&self) -> Option<SystemTime> {
+        self.header(IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| HttpDate::from_str(v).ok())
+            .map(SystemTime::from)
+    }
+
+    /// Whether the client's cached copy tagged `If-None-Match` is still `etag`, so the
+    /// handler can answer `Response::not_modified`.
+    pub fn etag_matches(&self, etag: &str) -> bool {
+        crate::header_util::etag_matches(self.if_none_match().as_deref(), etag)
+    }
+
+    fn header_value_list(&self, key: HeaderName) -> Option<String> {
+        let values: Vec<&str> = self.header_all(key).filter_map(|v| v.to_str().ok()).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(","))
+        }
+    }
+
     pub fn param(&self, key: &str) -> HttpResult<&str> {
         self.request.match_info().get(key).ok_or(http_err!(ErrorCode::NotFound, "missing parameter"))
     }
 
+    /// Deserializes all path params, e.g. `{ org: String, id: u64 }` for `/orgs/{org}/items/{id}`.
+    pub fn params<T: DeserializeOwned>(&self) -> HttpResult<T> {
+        self.request.match_info().load::<T>()
+            .map_err(|e| http_err!(ErrorCode::InvalidParam, "invalid path params: {}", e))
+    }
+
+    /// Deserializes the query string. The error names the offending parameter when it is known,
+    /// e.g. ``invalid query parameter `page`: invalid digit found in string``.
     pub fn query<T: DeserializeOwned>(&self) -> HttpResult<T> {
         let query = self.request.query_string();
-        serde_qs::from_str(query).map_err(into_http_err!(ErrorCode::InvalidParam, "failed to parse query"))
+        let de = serde_qs::Deserializer::new(query.as_bytes())
+            .map_err(|e| http_err!(ErrorCode::InvalidParam, "invalid query: {}", e))?;
+        serde_path_to_error::deserialize(de).map_err(|e| {
+            let path = e.path().to_string();
+            if path == "." {
+                http_err!(ErrorCode::InvalidParam, "invalid query: {}", e.inner())
+            } else {
+                http_err!(ErrorCode::InvalidParam, "invalid query parameter `{}`: {}", path, e.inner())
+            }
+        })
+    }
+
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        form_urlencoded::parse(self.request.query_string().as_bytes())
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    pub fn query_param_parse<T: FromStr>(&self, key: &str) -> HttpResult<Option<T>> {
+        match self.query_param(key) {
+            Some(value) => value.parse::<T>().map(Some)
+                .map_err(|_| http_err!(ErrorCode::InvalidParam, "invalid query param {}", key)),
+            None => Ok(None),
+        }
     }
 
     pub fn take_body(&mut self) -> Payload {
@@ -92,35 +355,207 @@ impl<State> Request<State> {
         }
     }
 
+    /// Replaces the body, e.g. to hand a body read by a middleware on to the endpoint.
+    /// The new body is not decoded again according to `Content-Encoding`.
+    pub fn set_body(&mut self, body: impl Into<web::Bytes>) {
+        self.body_decoded = true;
+        let body = body.into();
+        let stream: Pin<Box<dyn Stream<Item = Result<web::Bytes, actix_web::error::PayloadError>>>> = Box::pin(futures_util::stream::once(async move { Ok(body) }));
+        self.payload = Some(Payload::from(stream));
+    }
+
+    /// Reads the body as a string. Invalid utf-8 is rejected with `InvalidData` rather than replaced,
+    /// the same as tide's `Request::body_string`.
     pub async fn body_string(&mut self) -> HttpResult<String> {
         let content = self.body_bytes().await?;
         std::str::from_utf8(content.as_slice()).map_err(into_http_err!(ErrorCode::InvalidData, "Not a utf8 format string")).map(|s| s.to_string())
     }
 
+    pub fn content_length(&self) -> Option<u64> {
+        self.header(CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.trim().parse().ok())
+    }
+
+    pub fn max_body_size(&self) -> Option<usize> {
+        self.request.app_data::<EndpointConfig>().and_then(|v| v.max_body_size)
+    }
+
     pub async fn body_bytes(&mut self) -> HttpResult<Vec<u8>> {
+        let max_body_size = self.max_body_size();
+        self.body_bytes_limited(max_body_size).await
+    }
+
+    async fn body_bytes_limited(&mut self, max_body_size: Option<usize>) -> HttpResult<Vec<u8>> {
+        let content_length = self.content_length();
+        if let (Some(max_body_size), Some(content_length)) = (max_body_size, content_length) {
+            if content_length > max_body_size as u64 {
+                return Err(http_err!(ErrorCode::PayloadTooLarge, "body size {} exceeds the limit of {} bytes", content_length, max_body_size));
+            }
+        }
+
+        let capacity = content_length.map(|v| v.min(BODY_PREALLOCATE_LIMIT as u64) as usize).unwrap_or(0);
         let mut body = self.take_body();
-        let mut buf = web::BytesMut::new();
+        let mut buf = web::BytesMut::with_capacity(capacity);
         while let Some(chunk) = body.next().await {
             let chunk = chunk.map_err(into_http_err!(ErrorCode::ConnectFailed, "failed to read body"))?;
+            if let Some(max_body_size) = max_body_size {
+                if buf.len() + chunk.len() > max_body_size {
+                    return Err(http_err!(ErrorCode::PayloadTooLarge, "body size exceeds the limit of {} bytes", max_body_size));
+                }
+            }
             buf.extend_from_slice(&chunk);
         }
-        Ok(buf.to_vec())
+        self.decode_body(buf.to_vec(), max_body_size)
+    }
+
+    // decodes a gzip or deflate body, the decoded size is limited as well to stop compression bombs
+    fn decode_body(&self, body: Vec<u8>, max_body_size: Option<usize>) -> HttpResult<Vec<u8>> {
+        let decompress = self.request.app_data::<EndpointConfig>().map(|v| v.decompress_request).unwrap_or(true);
+        let encoding = match self.header(CONTENT_ENCODING).and_then(|v| v.to_str().ok()) {
+            Some(encoding) if decompress && !self.body_decoded => encoding.trim().to_ascii_lowercase(),
+            _ => return Ok(body),
+        };
+        let reader: Box<dyn std::io::Read + '_> = match encoding.as_str() {
+            "gzip" | "x-gzip" => Box::new(flate2::read::GzDecoder::new(body.as_slice())),
+            "deflate" => Box::new(flate2::read::ZlibDecoder::new(body.as_slice())),
+            // other codings, e.g. `br` or a list like `gzip, br`, are left to the endpoint
+            _ => return Ok(body),
+        };
+        let limit = max_body_size.unwrap_or(DEFAULT_DECOMPRESS_LIMIT);
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::Read::take(reader, limit as u64 + 1), &mut decoded)
+            .map_err(into_http_err!(ErrorCode::InvalidParam, "decode {} body failed", encoding))?;
+        if decoded.len() > limit {
+            return Err(http_err!(ErrorCode::PayloadTooLarge, "decoded body size exceeds the limit of {} bytes", limit));
+        }
+        Ok(decoded)
+    }
+
+    /// Calls `f` with each body chunk as it arrives, without keeping the body in memory, and
+    /// returns the number of bytes read. The chunks are not decompressed; the server's max body
+    /// size still applies. An error from `f` stops reading.
+    pub async fn for_each_chunk<F>(&mut self, mut f: F) -> HttpResult<u64>
+        where
+            F: FnMut(&[u8]) -> HttpResult<()> {
+        let max_body_size = self.max_body_size();
+        if let (Some(max_body_size), Some(content_length)) = (max_body_size, self.content_length()) {
+            if content_length > max_body_size as u64 {
+                return Err(http_err!(ErrorCode::PayloadTooLarge, "body size {} exceeds the limit of {} bytes", content_length, max_body_size));
+            }
+        }
+
+        let mut body = self.take_body();
+        let mut read = 0u64;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(into_http_err!(ErrorCode::ConnectFailed, "failed to read body"))?;
+            read += chunk.len() as u64;
+            if let Some(max_body_size) = max_body_size {
+                if read > max_body_size as u64 {
+                    return Err(http_err!(ErrorCode::PayloadTooLarge, "body size exceeds the limit of {} bytes", max_body_size));
+                }
+            }
+            f(&chunk)?;
+        }
+        Ok(read)
+    }
+
+    /// Streams the body into `path` chunk by chunk and returns the number of bytes written.
+    /// The partial file is removed when the body exceeds the server's max body size.
+    pub async fn body_to_file(&mut self, path: impl AsRef<Path>) -> HttpResult<u64> {
+        let path = path.as_ref().to_path_buf();
+        let max_body_size = self.max_body_size();
+        if let (Some(max_body_size), Some(content_length)) = (max_body_size, self.content_length()) {
+            if content_length > max_body_size as u64 {
+                return Err(http_err!(ErrorCode::PayloadTooLarge, "body size {} exceeds the limit of {} bytes", content_length, max_body_size));
+            }
+        }
+
+        let file_path = path.clone();
+        let mut file = block_io(move || std::fs::File::create(file_path)).await
+            .map_err(into_http_err!(ErrorCode::IOError, "create file {} failed", path.display()))?;
+        let mut body = self.take_body();
+        let mut written = 0u64;
+        let ret = async {
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(into_http_err!(ErrorCode::ConnectFailed, "failed to read body"))?;
+                written += chunk.len() as u64;
+                if let Some(max_body_size) = max_body_size {
+                    if written > max_body_size as u64 {
+                        return Err(http_err!(ErrorCode::PayloadTooLarge, "body size exceeds the limit of {} bytes", max_body_size));
+                    }
+                }
+                file = block_io(move || {
+                    std::io::Write::write_all(&mut file, &chunk)?;
+                    Ok(file)
+                }).await.map_err(into_http_err!(ErrorCode::IOError, "write file {} failed", path.display()))?;
+            }
+            block_io(move || file.sync_all()).await.map_err(into_http_err!(ErrorCode::IOError, "write file {} failed", path.display()))
+        }.await;
+        if let Err(e) = ret {
+            let file_path = path.clone();
+            let _ = block_io(move || std::fs::remove_file(file_path)).await;
+            return Err(e);
+        }
+        Ok(written)
     }
 
+    /// Reads the body as json, failing once more than `DEFAULT_JSON_LIMIT` (or the server's max body size) is read.
     pub async fn body_json<T: DeserializeOwned>(&mut self) -> HttpResult<T> {
-        let body = self.body_string().await?;
-        let json = serde_json::from_str(&body).map_err(|e| {
-            http_err!(ErrorCode::InvalidData, "parse data failed {}", e)
+        let max_bytes = self.max_body_size().unwrap_or(DEFAULT_JSON_LIMIT);
+        self.body_json_limited(max_bytes).await
+    }
+
+    pub async fn body_json_limited<T: DeserializeOwned>(&mut self, max_bytes: usize) -> HttpResult<T> {
+        let max_bytes = self.max_body_size().map(|v| v.min(max_bytes)).unwrap_or(max_bytes);
+        let body = self.body_bytes_limited(Some(max_bytes)).await?;
+        if body.is_empty() {
+            return Err(http_err!(ErrorCode::InvalidParam, "empty request body, expected JSON"));
+        }
+        let json = serde_json::from_slice(&body).map_err(|e| {
+            http_err!(ErrorCode::InvalidData, "parse data failed {}", crate::errors::json_error_msg(&body, &e))
         })?;
         Ok(json)
     }
 
+    /// Validates the json body against `schema` before deserializing it. The `InvalidParam`
+    /// error lists every validation error with its json pointer.
+    #[cfg(feature = "json_schema")]
+    pub async fn body_json_validated<T: DeserializeOwned>(&mut self, schema: &jsonschema::Validator) -> HttpResult<T> {
+        let value: serde_json::Value = self.body_json().await?;
+        let errors: Vec<String> = schema.iter_errors(&value)
+            .map(|e| format!("{}: {}", e.instance_path, e))
+            .collect();
+        if !errors.is_empty() {
+            return Err(http_err!(ErrorCode::InvalidParam, "invalid json body {}", errors.join("; ")));
+        }
+        serde_json::from_value(value).map_err(|e| {
+            http_err!(ErrorCode::InvalidData, "parse data failed {}", e)
+        })
+    }
+
     pub async fn body_form<T: DeserializeOwned>(&mut self) -> HttpResult<T> {
         let body = self.body_string().await?;
         serde_qs::from_str(&body).map_err(into_http_err!(ErrorCode::InvalidData, "parse data failed"))
     }
 }
 
+/// A json body extracted after checking the request content type, answering 415 like
+/// `ContentTypeMiddleware` when it isn't json.
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> Json<T> {
+    pub async fn from_request<State>(req: &mut Request<State>) -> HttpResult<Self> {
+        let mime = req.content_type().split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        if mime != "application/json" && !(mime.starts_with("application/") && mime.ends_with("+json")) {
+            return Err(http_err!(ErrorCode::UnsupportedMediaType, "unsupported content type '{}', expected json", mime));
+        }
+        Ok(Json(req.body_json().await?))
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
 pub struct Response {
     pub(crate) resp: Option<HttpResponse>,
 }
@@ -132,6 +567,62 @@ impl Response {
         }
     }
 
+    pub fn builder(status: StatusCode) -> ResponseBuilder {
+        ResponseBuilder {
+            builder: HttpResponse::build(status),
+        }
+    }
+
+    pub fn json<T: Serialize + ?Sized>(status: StatusCode, value: &T) -> HttpResult<Self> {
+        let body = serde_json::to_vec(value).map_err(into_http_err!(ErrorCode::InvalidData, "serialize json failed"))?;
+        Ok(Self::builder(status).content_type("application/json").body(body))
+    }
+
+    /// Streams the items as newline delimited json (`application/x-ndjson`).
+    pub fn ndjson<S, T>(status: StatusCode, stream: S) -> Self
+        where
+            S: Stream<Item = T> + 'static,
+            T: Serialize, {
+        let body = stream.map(|item| {
+            let mut line = serde_json::to_vec(&item)?;
+            line.push(b'\n');
+            Ok::<_, serde_json::Error>(web::Bytes::from(line))
+        });
+        Self::from(HttpResponse::build(status).content_type("application/x-ndjson").streaming(body))
+    }
+
+    /// Streams the items as a json array, serializing them as they are polled. An error before
+    /// the first item is answered with the error envelope; later errors abort the body, the
+    /// status has already been sent.
+    pub async fn from_result_stream<S, T, C>(status: StatusCode, stream: S) -> Self
+        where
+            S: Stream<Item = sfo_result::Result<T, C>> + 'static,
+            T: Serialize + 'static,
+            C: ResponseCode, {
+        let mut stream = Box::pin(stream);
+        let first = match stream.next().await {
+            Some(Ok(item)) => item,
+            Some(Err(err)) => return super::from_result_status::<(), C>(status, Err(err)),
+            None => return Self::builder(status).content_type("application/json").body("[]"),
+        };
+        let items = futures_util::stream::once(async { Ok(first) }).chain(stream);
+        let body = items.enumerate().map(|(i, item)| {
+            let item = item.map_err(|err| {
+                log::error!("json array stream failed {:?}", err);
+                std::io::Error::other(format!("{:?}", err))
+            })?;
+            let mut chunk = vec![if i == 0 { b'[' } else { b',' }];
+            serde_json::to_writer(&mut chunk, &item)?;
+            Ok::<_, std::io::Error>(web::Bytes::from(chunk))
+        }).chain(futures_util::stream::once(async { Ok(web::Bytes::from_static(b"]")) }));
+        Self::from(HttpResponse::build(status).content_type("application/json").streaming(body))
+    }
+
+    pub fn not_modified(etag: &str) -> HttpResult<Self> {
+        let etag = HeaderValue::from_str(etag).map_err(into_http_err!(ErrorCode::InvalidParam, "invalid etag"))?;
+        Ok(Self::builder(StatusCode::NOT_MODIFIED).header(ETAG, etag).build())
+    }
+
     pub fn status(&self) -> StatusCode {
         self.resp.as_ref().unwrap().status()
     }
@@ -153,6 +644,15 @@ impl Response {
             }
         }
     }
+    /// Length of the body to send, `None` for streamed bodies whose length is unknown.
+    pub fn body_len(&self) -> Option<u64> {
+        match self.resp.as_ref().unwrap().body().size() {
+            BodySize::None => Some(0),
+            BodySize::Sized(len) => Some(len),
+            BodySize::Stream => None,
+        }
+    }
+
     pub fn is_empty(&self) -> Option<bool> {
         self.len().map(|len| len == 0)
     }
@@ -161,18 +661,83 @@ impl Response {
         self.resp = Some(self.resp.take().unwrap().set_body(BoxBody::new(body)));
     }
 
+    /// Streams the body from `reader`. The length is unknown, so any `Content-Length` set before
+    /// is dropped and HTTP/1.1 clients receive the body chunked.
+    pub fn set_body_read(&mut self, reader: impl futures_util::AsyncRead + 'static) {
+        let stream = futures_util::stream::unfold(Some(Box::pin(reader)), |reader| async move {
+            let mut reader = reader?;
+            let mut buf = vec![0u8; 8192];
+            match reader.read(buf.as_mut_slice()).await {
+                Ok(0) => None,
+                Ok(n) => {
+                    buf.truncate(n);
+                    Some((Ok(web::Bytes::from(buf)), Some(reader)))
+                }
+                Err(e) => {
+                    log::error!("read response body failed.{}", e);
+                    Some((Err(e), None))
+                }
+            }
+        });
+        self.resp.as_mut().unwrap().headers_mut().remove(CONTENT_LENGTH);
+        self.set_body(actix_web::body::BodyStream::new(stream));
+    }
+
     pub fn insert_header(&mut self, name: HeaderName, value: HeaderValue) {
         self.resp.as_mut().unwrap().headers_mut().insert(name, value);
 
     }
 
+    /// Adds `name` to the `Vary` header, keeping the values already listed.
+    pub fn append_vary(&mut self, name: &str) -> HttpResult<()> {
+        let headers = self.resp.as_mut().unwrap().headers_mut();
+        let vary: Vec<&str> = headers.get_all(VARY).filter_map(|v| v.to_str().ok()).collect();
+        let vary = crate::header_util::append_vary(Some(vary.join(",").as_str()), name);
+        headers.insert(VARY, HeaderValue::from_str(vary.as_str()).map_err(into_http_err!(ErrorCode::InvalidParam, "invalid vary header"))?);
+        Ok(())
+    }
+
+    pub fn add_cookie(&mut self, cookie: &Cookie<'_>) -> HttpResult<()> {
+        self.resp.as_mut().unwrap().add_cookie(cookie)
+            .map_err(into_http_err!(ErrorCode::InvalidParam, "invalid cookie"))
+    }
+
     pub fn set_content_type(&mut self, content_type: &str) -> HttpResult<()> {
-        self.insert_header(HeaderName::from_static("Content-Type"), HeaderValue::from_str(content_type)
+        self.insert_header(CONTENT_TYPE, HeaderValue::from_str(content_type)
             .map_err(into_http_err!(ErrorCode::InvalidParam, "invalid content type"))?);
         Ok(())
     }
 }
 
+pub struct ResponseBuilder {
+    builder: HttpResponseBuilder,
+}
+
+impl ResponseBuilder {
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.builder.insert_header((name, value));
+        self
+    }
+
+    pub fn append_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.builder.append_header((name, value));
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.builder.content_type(content_type);
+        self
+    }
+
+    pub fn body<B: MessageBody + 'static>(mut self, body: B) -> Response {
+        Response::from(self.builder.body(body))
+    }
+
+    pub fn build(mut self) -> Response {
+        Response::from(self.builder.finish())
+    }
+}
+
 impl From<HttpResponse> for Response {
     fn from(resp: HttpResponse) -> Self {
         Self {
@@ -221,35 +786,26 @@ impl<State> Endpoint<State> for ServeDir
     async fn call(&self, req: Request<State>) -> HttpResult<Response> {
         let path = req.url().path();
         let path = path.strip_prefix(&self.prefix).unwrap();
-        let path = path.trim_start_matches('/');
-        let mut file_path = self.dir.clone();
-        for p in Path::new(path) {
-            if p == OsStr::new(".") {
-                continue;
-            } else if p == OsStr::new("..") {
-                file_path.pop();
-            } else {
-                file_path.push(&p);
+        let file_path = match crate::file_util::resolve_path(&self.dir, path) {
+            Some(file_path) => file_path,
+            None => {
+                log::warn!("Unauthorized attempt to read: {:?}", path);
+                return Ok(Response::new(StatusCode::FORBIDDEN));
             }
-        }
+        };
 
         log::info!("Requested file: {:?}", file_path);
 
-        if !file_path.starts_with(&self.dir) {
-            log::warn!("Unauthorized attempt to read: {:?}", file_path);
-            Ok(Response::new(StatusCode::FORBIDDEN))
-        } else {
-            match NamedFile::open_async(file_path.as_path()).await {
-                Ok(file) => {
-                    let resp = Response::from(file.into_response(req.request()));
-                    Ok(resp)
-                },
-                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                    log::warn!("File not found: {:?}", &file_path);
-                    Ok(Response::new(StatusCode::NOT_FOUND))
-                },
-                Err(e) => Err(http_err!(ErrorCode::IOError, "read file failed {}", e)),
-            }
+        match NamedFile::open_async(file_path.as_path()).await {
+            Ok(file) => {
+                let resp = Response::from(file.into_response(req.request()));
+                Ok(resp)
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log::warn!("File not found: {:?}", &file_path);
+                Ok(Response::new(StatusCode::NOT_FOUND))
+            },
+            Err(e) => Err(http_err!(ErrorCode::IOError, "read file failed {}", e)),
         }
     }
 }
@@ -285,6 +841,94 @@ impl<State: Clone + Send + Sync + 'static> Endpoint<State> for ServeFile {
     }
 }
 
+pub(crate) type AssetProvider = Arc<dyn Fn(&str) -> Option<(Cow<'static, [u8]>, &'static str)> + Send + Sync>;
+
+/// Serves the assets returned by a provider, e.g. files embedded with `rust-embed`, keyed by
+/// the path below the route prefix. The directory index is `index.html`.
+pub(crate) struct ServeEmbedded {
+    prefix: String,
+    provider: AssetProvider,
+}
+
+impl ServeEmbedded {
+    pub(crate) fn new(prefix: String, provider: AssetProvider) -> Self {
+        Self { prefix, provider }
+    }
+}
+
+fn asset_etag(data: &[u8]) -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    data.hash(&mut hasher);
+    format!("\"{:x}-{:x}\"", data.len(), hasher.finish())
+}
+
+#[async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for ServeEmbedded {
+    async fn call(&self, req: Request<State>) -> HttpResult<Response> {
+        let path = req.url().path();
+        let path = path.strip_prefix(&self.prefix).unwrap_or(path).trim_start_matches('/');
+        let path = if path.is_empty() || path.ends_with('/') {
+            format!("{}index.html", path)
+        } else {
+            path.to_string()
+        };
+        let (data, content_type) = match (self.provider)(path.as_str()) {
+            Some(asset) => asset,
+            None => {
+                log::warn!("Asset not found: {}", path);
+                return Ok(Response::new(StatusCode::NOT_FOUND));
+            }
+        };
+
+        let etag = asset_etag(data.as_ref());
+        if req.etag_matches(etag.as_str()) {
+            return Response::not_modified(etag.as_str());
+        }
+        let data = match data {
+            Cow::Borrowed(data) => web::Bytes::from_static(data),
+            Cow::Owned(data) => web::Bytes::from(data),
+        };
+        Ok(Response::builder(StatusCode::OK)
+            .content_type(content_type)
+            .header(ETAG, HeaderValue::from_str(etag.as_str()).map_err(into_http_err!(ErrorCode::ServerError, "invalid etag"))?)
+            .body(data))
+    }
+}
+
+pub(crate) struct PreflightEndpoint {
+    cors: CorsConfig,
+}
+
+impl PreflightEndpoint {
+    pub(crate) fn new(cors: CorsConfig) -> Self {
+        Self { cors }
+    }
+}
+
+#[async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for PreflightEndpoint {
+    async fn call(&self, req: Request<State>) -> HttpResult<Response> {
+        let mut resp = Response::new(StatusCode::NO_CONTENT);
+        resp.insert_header(ALLOW, HeaderValue::from_str(self.cors.get_allow_methods())
+            .map_err(into_http_err!(ErrorCode::InvalidParam, "invalid allow methods"))?);
+        let origin = match req.header(ORIGIN).and_then(|v| v.to_str().ok()) {
+            Some(origin) => origin.to_string(),
+            None => return Ok(resp),
+        };
+        if self.cors.is_allowed(origin.as_str()) {
+            let request_headers = req.header(ACCESS_CONTROL_REQUEST_HEADERS).and_then(|v| v.to_str().ok());
+            for (name, value) in self.cors.preflight_headers(origin.as_str(), request_headers) {
+                resp.insert_header(HeaderName::from_str(name).map_err(into_http_err!(ErrorCode::InvalidParam, "invalid cors header"))?,
+                                   HeaderValue::from_str(value.as_str()).map_err(into_http_err!(ErrorCode::InvalidParam, "invalid cors header"))?);
+            }
+        }
+        if self.cors.vary_origin() {
+            resp.append_vary("Origin")?;
+        }
+        Ok(resp)
+    }
+}
+
 #[derive(Clone)]
 pub struct EndpointHandler<State: Clone + Send + Sync + 'static> {
     ep: Pin<Arc<dyn Endpoint<State>>>,
@@ -312,16 +956,34 @@ impl<State> Service<ServiceRequest> for EndpointHandler<State> where State: 'sta
         let state = self.state.clone();
         let fut = async move {
             let (http_req, payload) = req.into_parts();
+            let token = CancellationToken::default();
+            http_req.extensions_mut().insert(token.clone());
+            let mut cancel_guard = CancelOnDrop(Some(token.clone()));
             let req = Request {
                 state,
                 request: http_req.clone(),
                 payload: Some(payload),
+                body_decoded: false,
             };
 
-            let res = ep.call(req).await.map_err(|e| {
-                let e: Box<dyn std::error::Error + 'static> = Box::new(e);
-                Self::Error::from(e)
-            })?;
+            let config = http_req.app_data::<EndpointConfig>().cloned().unwrap_or_default();
+            let call = with_error_detail(config.expose_error_detail, ep.call(req));
+            let ret = match config.request_timeout {
+                Some(timeout) => match actix_web::rt::time::timeout(timeout, call).await {
+                    Ok(ret) => ret,
+                    Err(_) => {
+                        log::warn!("request {} timeout", http_req.path());
+                        token.cancel();
+                        Ok(Response::new(StatusCode::REQUEST_TIMEOUT))
+                    }
+                },
+                None => call.await,
+            };
+            cancel_guard.disarm();
+            let res = match ret {
+                Ok(res) => res,
+                Err(e) => error_response(&e, &config, http_req.path()),
+            };
 
             Ok(ServiceResponse::new(http_req, res.resp.unwrap()))
         };
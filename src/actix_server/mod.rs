@@ -1,10 +1,40 @@
+mod access_log;
 mod actix_server;
+mod basic_auth;
+mod catch_panic;
+mod concurrency_limit;
+mod content_type;
+#[cfg(feature = "body_tap")]
+mod body_tap;
 mod endpoint;
+mod multipart;
+mod proxy;
+#[cfg(feature = "tracing")]
+mod request_span;
+mod route_middleware;
 mod router;
+mod security_headers;
+mod session;
+mod validate;
 
 use actix_web::http::header::COOKIE;
+pub use access_log::*;
 pub use actix_server::*;
+pub use basic_auth::*;
+pub use catch_panic::*;
+pub use concurrency_limit::*;
+pub use content_type::*;
+#[cfg(feature = "body_tap")]
+pub use body_tap::*;
 pub use endpoint::*;
+pub use multipart::*;
+pub use proxy::*;
+#[cfg(feature = "tracing")]
+pub use request_span::*;
+pub use route_middleware::*;
+pub use security_headers::*;
+pub use session::*;
+pub use validate::*;
 use crate::http_util::header::ToStrError;
 
 pub fn get_cookie<'a, STATE>(req: &'a Request<STATE>, cookie_name: &str) -> Option<String> {
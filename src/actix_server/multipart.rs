@@ -0,0 +1,89 @@
+use actix_multipart::Multipart;
+use futures_util::StreamExt;
+use crate::errors::{ErrorCode, http_err, HttpResult};
+use super::Request;
+
+/// Limits enforced while a multipart body is streamed, so oversized uploads are rejected
+/// before they are buffered.
+#[derive(Clone, Debug)]
+pub struct MultipartLimits {
+    max_parts: usize,
+    max_file_size: usize,
+    max_total_size: usize,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_parts: 16,
+            max_file_size: 8 * 1024 * 1024,
+            max_total_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl MultipartLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_max_parts(mut self, max_parts: usize) -> Self {
+        self.max_parts = max_parts;
+        self
+    }
+
+    pub fn set_max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn set_max_total_size(mut self, max_total_size: usize) -> Self {
+        self.max_total_size = max_total_size;
+        self
+    }
+}
+
+pub struct MultipartField {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl<State> Request<State> {
+    pub async fn body_multipart(&mut self, limits: &MultipartLimits) -> HttpResult<Vec<MultipartField>> {
+        let payload = self.take_body();
+        let mut multipart = Multipart::new(self.request().headers(), payload);
+        let mut fields = Vec::new();
+        let mut total_size = 0;
+        while let Some(field) = multipart.next().await {
+            let mut field = field.map_err(|e| http_err!(ErrorCode::InvalidParam, "invalid multipart body {}", e))?;
+            if fields.len() >= limits.max_parts {
+                return Err(http_err!(ErrorCode::InvalidParam, "multipart parts exceed the limit {}", limits.max_parts));
+            }
+            let name = field.name().unwrap_or("").to_string();
+            let file_name = field.content_disposition().and_then(|v| v.get_filename()).map(|v| v.to_string());
+            let content_type = field.content_type().map(|v| v.to_string());
+
+            let mut data = Vec::new();
+            while let Some(chunk) = field.next().await {
+                let chunk = chunk.map_err(|e| http_err!(ErrorCode::InvalidParam, "invalid multipart body {}", e))?;
+                if data.len() + chunk.len() > limits.max_file_size {
+                    return Err(http_err!(ErrorCode::PayloadTooLarge, "multipart part {} exceeds the limit of {} bytes", name, limits.max_file_size));
+                }
+                total_size += chunk.len();
+                if total_size > limits.max_total_size {
+                    return Err(http_err!(ErrorCode::PayloadTooLarge, "multipart body exceeds the limit of {} bytes", limits.max_total_size));
+                }
+                data.extend_from_slice(&chunk);
+            }
+            fields.push(MultipartField {
+                name,
+                file_name,
+                content_type,
+                data,
+            });
+        }
+        Ok(fields)
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use actix_web::body::{BodyStream, SizedStream};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{HeaderName, HeaderValue, CONNECTION, CONTENT_LENGTH, HOST};
+use actix_web::web;
+use futures_util::StreamExt;
+use crate::errors::{ErrorCode, http_err, HttpResult};
+use crate::http_util::{self, HttpClient, HttpClientBuilder};
+use super::{Endpoint, Request, Response};
+use super::content_type::has_body;
+
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+// hop-by-hop headers, including the ones listed in `Connection`, are not forwarded
+fn is_hop_by_hop(name: &str, connection: &[String]) -> bool {
+    HOP_BY_HOP_HEADERS.contains(&name) || connection.iter().any(|v| v.eq_ignore_ascii_case(name))
+}
+
+// Streams the body to the upstream request. The payload isn't `Send`, so a local task reads it
+// into a channel, failing the upstream request once more than `max_body_size` is read.
+fn forward_body(mut payload: Payload, max_body_size: Option<usize>, too_large: Arc<AtomicBool>) -> http_util::Body {
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<web::Bytes, std::io::Error>>(8);
+    actix_web::rt::spawn(async move {
+        let mut read = 0;
+        while let Some(chunk) = payload.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    let _ = tx.send(Err(std::io::Error::other(e.to_string()))).await;
+                    return;
+                }
+            };
+            read += chunk.len();
+            if max_body_size.map(|v| read > v).unwrap_or(false) {
+                too_large.store(true, Ordering::SeqCst);
+                let _ = tx.send(Err(std::io::Error::other("body too large"))).await;
+                return;
+            }
+            if tx.send(Ok(chunk)).await.is_err() {
+                return;
+            }
+        }
+    });
+    http_util::Body::wrap_stream(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }))
+}
+
+/// Forwards requests to an upstream server and streams its response back, so a route can act
+/// as a gateway. The forwarded path is the `tail` parameter of the route, e.g. `/api/*`, or the
+/// whole request path when the route has none. Request bodies are streamed upstream, limited by
+/// the server's max body size. Redirects are passed to the client, not followed.
+pub struct ProxyEndpoint {
+    upstream: String,
+    client: HttpClient,
+}
+
+impl ProxyEndpoint {
+    pub fn new(upstream: &str) -> Self {
+        let client = HttpClientBuilder::default()
+            .set_redirect_policy(http_util::redirect::Policy::none())
+            .set_auto_decompress(false)
+            .build();
+        Self::with_client(upstream, client)
+    }
+
+    pub fn with_client(upstream: &str, client: HttpClient) -> Self {
+        Self {
+            upstream: upstream.trim_end_matches('/').to_string(),
+            client,
+        }
+    }
+
+    fn upstream_url<State>(&self, req: &Request<State>) -> String {
+        let path = match req.param("tail") {
+            Ok(tail) => tail.to_string(),
+            Err(_) => req.request().path().to_string(),
+        };
+        let mut url = format!("{}/{}", self.upstream, path.trim_start_matches('/'));
+        if !req.request().query_string().is_empty() {
+            url.push('?');
+            url.push_str(req.request().query_string());
+        }
+        url
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for ProxyEndpoint {
+    async fn call(&self, mut req: Request<State>) -> HttpResult<Response> {
+        let url = self.upstream_url(&req);
+        let method = match http_util::Method::from_bytes(req.method().as_str().as_bytes()) {
+            Ok(method) => method,
+            Err(_) => {
+                log::warn!("proxy to {} with unsupported method {}", url, req.method());
+                return Ok(Response::new(StatusCode::METHOD_NOT_ALLOWED));
+            }
+        };
+        let connection: Vec<String> = req.header_all(CONNECTION)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(',').map(|v| v.trim().to_string()).collect::<Vec<_>>())
+            .collect();
+
+        let mut builder = self.client.request(method, url.as_str());
+        for (name, value) in req.request().headers().iter() {
+            if name == HOST || is_hop_by_hop(name.as_str(), &connection) {
+                continue;
+            }
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+        let mut forwarded_for: Vec<&str> = req.header_all(HeaderName::from_static("x-forwarded-for")).filter_map(|v| v.to_str().ok()).collect();
+        let peer = req.request().peer_addr().map(|addr| addr.ip().to_string());
+        if let Some(peer) = peer.as_ref() {
+            forwarded_for.push(peer.as_str());
+        }
+        if !forwarded_for.is_empty() {
+            builder = builder.header("x-forwarded-for", forwarded_for.join(", "));
+        }
+        if let Some(scheme) = req.scheme() {
+            builder = builder.header("x-forwarded-proto", scheme);
+        }
+        let max_body_size = req.max_body_size();
+        if let (Some(max_body_size), Some(content_length)) = (max_body_size, req.content_length()) {
+            if content_length > max_body_size as u64 {
+                return Err(http_err!(ErrorCode::PayloadTooLarge, "body size {} exceeds the limit of {} bytes", content_length, max_body_size));
+            }
+        }
+        let too_large = Arc::new(AtomicBool::new(false));
+        if has_body(&req) {
+            builder = builder.body(forward_body(req.take_body(), max_body_size, too_large.clone()));
+        }
+        let upstream_req = builder.build().map_err(|e| {
+            http_err!(ErrorCode::InvalidParam, "build proxy request to {} failed {}", url, e)
+        })?;
+
+        let upstream_resp = match self.client.execute(upstream_req).await {
+            Ok(resp) => resp,
+            Err(_) if too_large.load(Ordering::SeqCst) => {
+                return Err(http_err!(ErrorCode::PayloadTooLarge, "body size exceeds the limit of {} bytes", max_body_size.unwrap_or_default()));
+            }
+            Err(e) => {
+                log::error!("proxy to {} failed {:?}", url, e);
+                let status = if e.code() == ErrorCode::Timeout { StatusCode::GATEWAY_TIMEOUT } else { StatusCode::BAD_GATEWAY };
+                return Ok(Response::new(status));
+            }
+        };
+
+        let status = StatusCode::from_u16(upstream_resp.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+        let connection: Vec<String> = upstream_resp.headers().get_all("connection").iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(',').map(|v| v.trim().to_string()).collect::<Vec<_>>())
+            .collect();
+        let mut builder = actix_web::HttpResponse::build(status);
+        for (name, value) in upstream_resp.headers().iter() {
+            if name == "content-length" || is_hop_by_hop(name.as_str(), &connection) {
+                continue;
+            }
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_str().as_bytes()), HeaderValue::from_bytes(value.as_bytes())) {
+                builder.append_header((name, value));
+            }
+        }
+
+        let content_length = upstream_resp.content_length();
+        let stream = futures_util::stream::unfold(Some(upstream_resp), |resp| async move {
+            let mut resp = resp?;
+            match resp.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(web::Bytes::from(chunk.to_vec())), Some(resp))),
+                Ok(None) => None,
+                Err(e) => {
+                    log::error!("read proxy response failed {}", e);
+                    Some((Err(e), None))
+                }
+            }
+        });
+        let resp = match content_length {
+            Some(len) => builder.body(SizedStream::new(len, Box::pin(stream))),
+            None => builder.body(BodyStream::new(stream)),
+        };
+        Ok(Response::from(resp))
+    }
+}
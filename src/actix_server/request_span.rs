@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use actix_web::http::header::HeaderName;
+use tracing::Instrument;
+use crate::errors::HttpResult;
+use super::{error_status, Middleware, Next, Request, Response};
+
+/// Runs the wrapped endpoints inside an `http_request` tracing span with `method`, `path`,
+/// `request_id` and, once answered, `status` fields. The request id is taken from the
+/// `X-Request-Id` header when present, otherwise a process local sequence number is used.
+pub struct RequestSpanMiddleware {
+    next_id: AtomicU64,
+}
+
+impl RequestSpanMiddleware {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+        }
+    }
+}
+
+impl Default for RequestSpanMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for RequestSpanMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let request_id = match req.header(HeaderName::from_static("x-request-id")).and_then(|v| v.to_str().ok()) {
+            Some(request_id) => request_id.to_string(),
+            None => self.next_id.fetch_add(1, Ordering::Relaxed).to_string(),
+        };
+        let span = tracing::info_span!("http_request",
+            method = %req.method(),
+            path = %req.request().path(),
+            request_id = %request_id,
+            status = tracing::field::Empty);
+
+        let ret = next.run(req).instrument(span.clone()).await;
+        let status = match ret.as_ref() {
+            Ok(resp) => resp.status(),
+            Err(e) => error_status(e.code()),
+        };
+        span.record("status", status.as_u16());
+        ret
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+use crate::errors::HttpResult;
+use super::{Endpoint, Request, Response};
+
+/// Middleware that wraps around the remaining middleware chain of a route.
+#[async_trait::async_trait(?Send)]
+pub trait Middleware<State: Clone + Send + Sync + 'static>: Send + Sync + 'static {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response>;
+}
+
+/// The remainder of a middleware chain, including the endpoint.
+pub struct Next<'a, State: Clone + Send + Sync + 'static> {
+    endpoint: &'a dyn Endpoint<State>,
+    next_middleware: &'a [Arc<dyn Middleware<State>>],
+}
+
+impl<State: Clone + Send + Sync + 'static> Next<'_, State> {
+    pub async fn run(mut self, req: Request<State>) -> HttpResult<Response> {
+        if let Some((current, next)) = self.next_middleware.split_first() {
+            self.next_middleware = next;
+            current.handle(req, self).await
+        } else {
+            self.endpoint.call(req).await
+        }
+    }
+}
+
+pub(crate) struct MiddlewareEndpoint<State: Clone + Send + Sync + 'static> {
+    endpoint: Box<dyn Endpoint<State>>,
+    middleware: Vec<Arc<dyn Middleware<State>>>,
+}
+
+impl<State: Clone + Send + Sync + 'static> MiddlewareEndpoint<State> {
+    pub(crate) fn wrap_with_middleware(ep: impl Endpoint<State>, middleware: &[Arc<dyn Middleware<State>>]) -> Self {
+        Self {
+            endpoint: Box::new(ep),
+            middleware: middleware.to_vec(),
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Endpoint<State> for MiddlewareEndpoint<State> {
+    async fn call(&self, req: Request<State>) -> HttpResult<Response> {
+        let next = Next {
+            endpoint: self.endpoint.as_ref(),
+            next_middleware: &self.middleware,
+        };
+        next.run(req).await
+    }
+}
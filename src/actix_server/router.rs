@@ -1,16 +1,32 @@
+use std::borrow::Cow;
 use std::future::Future;
 use std::path::Path;
 use std::sync::Arc;
-use actix_web::dev::{fn_factory, Service, ServiceRequest, ServiceResponse};
-use actix_web::http::Method;
+use actix_web::dev::{fn_factory, ResourceDef, Service, ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::http::header::{ALLOW, HeaderValue};
+use actix_web::HttpResponse;
 use futures_util::future::LocalBoxFuture;
 use crate::errors::{HttpResult, into_http_err};
-use super::{Endpoint, EndpointHandler, Response, ServeDir, ServeFile};
+use super::{Endpoint, EndpointHandler, Middleware, MiddlewareEndpoint, Response, ServeDir, ServeEmbedded, ServeFile};
+
+/// Converts `*name` wildcard segments into actix tail matches, so the rest of the path
+/// can be read with `req.param("name")`. A bare `*` is captured as `tail`.
+fn route_path(path: &str) -> String {
+    path.split('/').map(|segment| {
+        match segment.strip_prefix('*') {
+            Some("") => "{tail:.*}".to_string(),
+            Some(name) => format!("{{{}:.*}}", name),
+            None => segment.to_string(),
+        }
+    }).collect::<Vec<_>>().join("/")
+}
 
 pub struct Route<'a, State: 'static + Clone + Send + Sync> {
     path: String,
     state: State,
     route_list: &'a mut Vec<(Method, String, EndpointHandler<State>)>,
+    middleware: Vec<Arc<dyn Middleware<State>>>,
 }
 
 impl<'a, State> Route<'a, State>
@@ -20,42 +36,232 @@ impl<'a, State> Route<'a, State>
                state: State,
                route_list: &'a mut Vec<(Method, String, EndpointHandler<State>)>,) -> Route<State> {
         Route {
-            path,
+            path: route_path(path.as_str()),
+            state,
+            route_list,
+            middleware: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_middleware(path: String,
+                                  state: State,
+                                  route_list: &'a mut Vec<(Method, String, EndpointHandler<State>)>,
+                                  middleware: Vec<Arc<dyn Middleware<State>>>) -> Route<'a, State> {
+        Route {
+            path: route_path(path.as_str()),
             state,
             route_list,
+            middleware,
         }
     }
 
+    /// Apply the middleware to the endpoints registered on this route afterwards.
+    pub fn with(&mut self, middleware: impl Middleware<State>) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    fn handler(&self, ep: impl Endpoint<State>) -> EndpointHandler<State> {
+        EndpointHandler::new(self.state.clone(), MiddlewareEndpoint::wrap_with_middleware(ep, &self.middleware))
+    }
+
     pub fn get(&mut self, ep: impl Endpoint<State>) -> &mut Self {
-        self.route_list.push((Method::GET, self.path.clone(), EndpointHandler::new(self.state.clone(), ep)));
+        self.route_list.push((Method::GET, self.path.clone(), self.handler(ep)));
         self
     }
 
     pub fn post(&mut self, ep: impl Endpoint<State>) -> &mut Self {
-        self.route_list.push((Method::POST, self.path.clone(), EndpointHandler::new(self.state.clone(), ep)));
+        self.route_list.push((Method::POST, self.path.clone(), self.handler(ep)));
         self
     }
 
     pub fn put(&mut self, ep: impl Endpoint<State>) -> &mut Self {
-        self.route_list.push((Method::PUT, self.path.clone(), EndpointHandler::new(self.state.clone(), ep)));
+        self.route_list.push((Method::PUT, self.path.clone(), self.handler(ep)));
         self
     }
 
     pub fn delete(&mut self, ep: impl Endpoint<State>) -> &mut Self {
-        self.route_list.push((Method::DELETE, self.path.clone(), EndpointHandler::new(self.state.clone(), ep)));
+        self.route_list.push((Method::DELETE, self.path.clone(), self.handler(ep)));
         self
     }
 
+    pub fn method(&mut self, method: Method, ep: impl Endpoint<State>) -> &mut Self {
+        self.route_list.push((method, self.path.clone(), self.handler(ep)));
+        self
+    }
+
+    /// Register one endpoint for each of `methods`.
+    pub fn methods(&mut self, methods: &[Method], ep: impl Endpoint<State>) -> &mut Self {
+        let handler = self.handler(ep);
+        for method in methods {
+            self.route_list.push((method.clone(), self.path.clone(), handler.clone()));
+        }
+        self
+    }
+
+    /// Register the endpoint for every standard method, e.g. for a proxy.
+    pub fn all(&mut self, ep: impl Endpoint<State>) -> &mut Self {
+        self.methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE, Method::PATCH, Method::OPTIONS, Method::TRACE, Method::CONNECT], ep)
+    }
+
     pub fn serve_dir(&mut self, dir: impl AsRef<Path>) -> HttpResult<&mut Self> {
         let dir = dir.as_ref().to_path_buf().canonicalize()
             .map_err(into_http_err!(crate::errors::ErrorCode::IOError, "serve_dir failed"))?;
         let prefix = self.path.clone();
-        self.route_list.push((Method::GET, format!("{}/{{tail:.*}}", prefix.clone()), EndpointHandler::new(self.state.clone(), ServeDir::new(prefix, dir))));
+        self.route_list.push((Method::GET, format!("{}/{{tail:.*}}", prefix.clone()), self.handler(ServeDir::new(prefix, dir))));
         Ok(self)
     }
 
+    /// Serve the assets of `provider` below this route, e.g. `rust-embed` files. The provider gets
+    /// the path relative to the route and returns the file data with its content type.
+    pub fn serve_embedded(&mut self, provider: impl Fn(&str) -> Option<(Cow<'static, [u8]>, &'static str)> + Send + Sync + 'static) -> &mut Self {
+        let prefix = self.path.clone();
+        self.route_list.push((Method::GET, format!("{}/{{tail:.*}}", prefix.clone()), self.handler(ServeEmbedded::new(prefix, Arc::new(provider)))));
+        self
+    }
+
     pub fn serve_file(&mut self, file: impl AsRef<Path>) -> HttpResult<&mut Self> {
-        self.route_list.push((Method::GET, self.path.clone(), EndpointHandler::new(self.state.clone(), ServeFile::init(file.as_ref().to_path_buf())?)));
+        self.route_list.push((Method::GET, self.path.clone(), self.handler(ServeFile::init(file.as_ref().to_path_buf())?)));
         Ok(self)
     }
 }
+
+/// A group of routes sharing a path prefix and a middleware stack.
+pub struct Scope<'a, State: 'static + Clone + Send + Sync> {
+    prefix: String,
+    state: State,
+    route_list: &'a mut Vec<(Method, String, EndpointHandler<State>)>,
+    middleware: Vec<Arc<dyn Middleware<State>>>,
+}
+
+impl<'a, State> Scope<'a, State>
+    where
+        State: 'static + Clone + Send + Sync, {
+    pub fn new(prefix: String,
+               state: State,
+               route_list: &'a mut Vec<(Method, String, EndpointHandler<State>)>,) -> Scope<'a, State> {
+        Scope {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            state,
+            route_list,
+            middleware: Vec::new(),
+        }
+    }
+
+    pub(crate) fn with_middleware(prefix: String,
+                                  state: State,
+                                  route_list: &'a mut Vec<(Method, String, EndpointHandler<State>)>,
+                                  middleware: Vec<Arc<dyn Middleware<State>>>) -> Scope<'a, State> {
+        Scope {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            state,
+            route_list,
+            middleware,
+        }
+    }
+
+    /// Apply the middleware to the routes created from this scope afterwards.
+    pub fn with(&mut self, middleware: impl Middleware<State>) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn at(&mut self, path: &str) -> Route<'_, State> {
+        Route::with_middleware(format!("{}{}", self.prefix, path), self.state.clone(), self.route_list, self.middleware.clone())
+    }
+
+    pub fn scope(&mut self, prefix: &str) -> Scope<'_, State> {
+        Scope {
+            prefix: format!("{}{}", self.prefix, prefix.trim_end_matches('/')),
+            state: self.state.clone(),
+            route_list: self.route_list,
+            middleware: self.middleware.clone(),
+        }
+    }
+}
+
+/// Default service of the app: answers 405 with the `Allow` header when the path is routed for
+/// other methods and 404 otherwise, through the configured handlers when set.
+#[derive(Clone)]
+pub(crate) struct FallbackHandler<State: Clone + Send + Sync + 'static> {
+    pub(crate) paths: Arc<Vec<(ResourceDef, Vec<Method>)>>,
+    pub(crate) not_found: Option<EndpointHandler<State>>,
+    pub(crate) method_not_allowed: Option<EndpointHandler<State>>,
+}
+
+impl<State: Clone + Send + Sync + 'static> FallbackHandler<State> {
+    pub(crate) fn new(routes: &[(Method, String, EndpointHandler<State>)],
+                      not_found: Option<EndpointHandler<State>>,
+                      method_not_allowed: Option<EndpointHandler<State>>) -> Self {
+        let mut paths: Vec<(ResourceDef, Vec<Method>)> = Vec::new();
+        for (method, path, _) in routes.iter() {
+            let methods = match paths.iter_mut().find(|(def, _)| def.pattern() == Some(path.as_str())) {
+                Some((_, methods)) => methods,
+                None => {
+                    paths.push((ResourceDef::new(path.as_str()), Vec::new()));
+                    &mut paths.last_mut().unwrap().1
+                }
+            };
+            methods.push(method.clone());
+            // GET routes answer HEAD as well
+            if method == Method::GET {
+                methods.push(Method::HEAD);
+            }
+        }
+        Self {
+            paths: Arc::new(paths),
+            not_found,
+            method_not_allowed,
+        }
+    }
+
+    fn allowed_methods(&self, path: &str) -> Vec<&Method> {
+        let mut allowed: Vec<&Method> = Vec::new();
+        for (_, methods) in self.paths.iter().filter(|(def, _)| def.is_match(path)) {
+            for method in methods.iter() {
+                if !allowed.contains(&method) {
+                    allowed.push(method);
+                }
+            }
+        }
+        allowed
+    }
+}
+
+impl<State> Service<ServiceRequest> for FallbackHandler<State> where State: 'static + Clone + Send + Sync {
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    actix_web::dev::always_ready!();
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let allowed = self.allowed_methods(req.path());
+        if allowed.is_empty() {
+            return match self.not_found.as_ref() {
+                Some(handler) => handler.call(req),
+                None => Box::pin(async move {
+                    Ok(req.into_response(HttpResponse::new(StatusCode::NOT_FOUND)))
+                }),
+            };
+        }
+
+        let allow = allowed.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(", ");
+        let allow = HeaderValue::from_str(allow.as_str()).ok();
+        let fut = match self.method_not_allowed.as_ref() {
+            Some(handler) => handler.call(req),
+            None => Box::pin(async move {
+                Ok(req.into_response(HttpResponse::new(StatusCode::METHOD_NOT_ALLOWED)))
+            }),
+        };
+        Box::pin(async move {
+            let mut resp = fut.await?;
+            if let Some(allow) = allow {
+                if !resp.headers().contains_key(ALLOW) {
+                    resp.headers_mut().insert(ALLOW, allow);
+                }
+            }
+            Ok(resp)
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use actix_web::http::header::{CONTENT_SECURITY_POLICY, HeaderName, HeaderValue, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS};
+use crate::errors::{ErrorCode, HttpResult, into_http_err};
+use super::{Middleware, Next, Request, Response};
+
+/// Adds common security headers to responses that don't set them already. Every header can be
+/// disabled by setting it to `None`; `Content-Security-Policy` is off by default.
+#[derive(Clone, Debug)]
+pub struct SecurityHeadersMiddleware {
+    strict_transport_security: Option<String>,
+    content_type_options: Option<String>,
+    frame_options: Option<String>,
+    referrer_policy: Option<String>,
+    content_security_policy: Option<String>,
+}
+
+impl Default for SecurityHeadersMiddleware {
+    fn default() -> Self {
+        Self {
+            strict_transport_security: Some("max-age=31536000; includeSubDomains".to_string()),
+            content_type_options: Some("nosniff".to_string()),
+            frame_options: Some("DENY".to_string()),
+            referrer_policy: Some("strict-origin-when-cross-origin".to_string()),
+            content_security_policy: None,
+        }
+    }
+}
+
+impl SecurityHeadersMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_strict_transport_security(mut self, value: Option<&str>) -> Self {
+        self.strict_transport_security = value.map(|v| v.to_string());
+        self
+    }
+
+    pub fn set_content_type_options(mut self, value: Option<&str>) -> Self {
+        self.content_type_options = value.map(|v| v.to_string());
+        self
+    }
+
+    pub fn set_frame_options(mut self, value: Option<&str>) -> Self {
+        self.frame_options = value.map(|v| v.to_string());
+        self
+    }
+
+    pub fn set_referrer_policy(mut self, value: Option<&str>) -> Self {
+        self.referrer_policy = value.map(|v| v.to_string());
+        self
+    }
+
+    pub fn set_content_security_policy(mut self, value: Option<&str>) -> Self {
+        self.content_security_policy = value.map(|v| v.to_string());
+        self
+    }
+
+    fn headers(&self) -> Vec<(HeaderName, &String)> {
+        [
+            (STRICT_TRANSPORT_SECURITY, self.strict_transport_security.as_ref()),
+            (X_CONTENT_TYPE_OPTIONS, self.content_type_options.as_ref()),
+            (X_FRAME_OPTIONS, self.frame_options.as_ref()),
+            (REFERRER_POLICY, self.referrer_policy.as_ref()),
+            (CONTENT_SECURITY_POLICY, self.content_security_policy.as_ref()),
+        ].into_iter().filter_map(|(name, value)| value.map(|v| (name, v))).collect()
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for SecurityHeadersMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let mut resp = next.run(req).await?;
+        for (name, value) in self.headers() {
+            if resp.resp.as_ref().unwrap().headers().contains_key(&name) {
+                continue;
+            }
+            let value = HeaderValue::from_str(value.as_str())
+                .map_err(into_http_err!(ErrorCode::InvalidParam, "invalid security header {}", name))?;
+            resp.insert_header(name, value);
+        }
+        Ok(resp)
+    }
+}
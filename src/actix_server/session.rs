@@ -0,0 +1,114 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::HttpMessage;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::errors::{ErrorCode, HttpResult, into_http_err};
+use crate::token_helper::{Algorithm, DecodingKey, EncodingKey, JsonWebToken};
+use super::{Middleware, Next, Request, Response};
+
+struct SessionData<T>(Option<T>);
+
+impl<State> Request<State> {
+    pub fn session<T: Clone + 'static>(&self) -> Option<T> {
+        self.request().extensions().get::<SessionData<T>>().and_then(|v| v.0.clone())
+    }
+
+    pub fn set_session<T: 'static>(&self, session: T) {
+        self.request().extensions_mut().insert(SessionData(Some(session)));
+    }
+
+    pub fn remove_session<T: 'static>(&self) {
+        self.request().extensions_mut().insert(SessionData::<T>(None));
+    }
+}
+
+/// Keeps a `T` session in a cookie signed as a HS256 JWT. The session is read with
+/// `Request::session` and changed with `Request::set_session`/`Request::remove_session`.
+/// The cookie is `HttpOnly`, `Secure` and `SameSite=Lax` by default.
+pub struct SessionMiddleware<T> {
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    _session: PhantomData<fn() -> T>,
+}
+
+impl<T> SessionMiddleware<T> {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            cookie_name: "sfo_session".to_string(),
+            ttl: Duration::from_secs(24 * 3600),
+            secure: true,
+            same_site: SameSite::Lax,
+            encoding_key: EncodingKey::from_secret(key),
+            decoding_key: DecodingKey::from_secret(key),
+            _session: Default::default(),
+        }
+    }
+
+    pub fn set_cookie_name(mut self, cookie_name: impl Into<String>) -> Self {
+        self.cookie_name = cookie_name.into();
+        self
+    }
+
+    pub fn set_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Send the cookie over https only, on by default. Turn it off for plain http deployments.
+    pub fn set_secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn set_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    fn build_cookie(&self, value: String, max_age: Duration) -> Cookie<'static> {
+        Cookie::build(self.cookie_name.clone(), value)
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .max_age(actix_web::cookie::time::Duration::seconds(max_age.as_secs() as i64))
+            .finish()
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State, T> Middleware<State> for SessionMiddleware<T>
+    where
+        State: Clone + Send + Sync + 'static,
+        T: Serialize + DeserializeOwned + Clone + 'static, {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> HttpResult<Response> {
+        let http_req = req.request().clone();
+        let cookie = http_req.cookie(self.cookie_name.as_str());
+        if let Some(cookie) = cookie.as_ref() {
+            match JsonWebToken::decode::<T>(cookie.value(), &self.decoding_key) {
+                Ok(session) => req.set_session(session),
+                Err(e) => log::warn!("invalid session cookie {}", e),
+            }
+        }
+
+        let mut resp = next.run(req).await?;
+
+        let session = http_req.extensions_mut().remove::<SessionData<T>>().and_then(|v| v.0);
+        if let Some(session) = session {
+            let expired_at = Utc::now() + chrono::Duration::seconds(self.ttl.as_secs() as i64);
+            let token = JsonWebToken::encode(Algorithm::HS256, session, expired_at, &self.encoding_key)
+                .map_err(into_http_err!(ErrorCode::ServerError, "encode session failed"))?;
+            resp.add_cookie(&self.build_cookie(token, self.ttl))?;
+        } else if cookie.is_some() {
+            resp.add_cookie(&self.build_cookie(String::new(), Duration::ZERO))?;
+        }
+        Ok(resp)
+    }
+}
@@ -0,0 +1,95 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use serde::de::DeserializeOwned;
+use crate::errors::{ErrorCode, http_err, HttpResult};
+use super::{Endpoint, Request, Response};
+
+/// Input checked after deserializing. Implemented for every `validator::Validate` type with
+/// the `validator` feature.
+pub trait Validate {
+    /// Returns one message per invalid field.
+    fn validate(&self) -> Result<(), Vec<String>>;
+}
+
+#[cfg(feature = "validator")]
+impl<T: validator::Validate> Validate for T {
+    fn validate(&self) -> Result<(), Vec<String>> {
+        validator::Validate::validate(self).map_err(|errors| {
+            let mut messages: Vec<String> = errors.field_errors().iter().map(|(field, errors)| {
+                let reasons: Vec<String> = errors.iter().map(|e| match e.message.as_ref() {
+                    Some(message) => message.to_string(),
+                    None => e.code.to_string(),
+                }).collect();
+                format!("{}: {}", field, reasons.join(", "))
+            }).collect();
+            messages.sort();
+            messages
+        })
+    }
+}
+
+fn check<T: Validate>(value: T) -> HttpResult<T> {
+    match value.validate() {
+        Ok(()) => Ok(value),
+        Err(errors) => Err(http_err!(ErrorCode::InvalidParam, "invalid input {}", errors.join("; "))),
+    }
+}
+
+/// Endpoint deserializing the json body into `T` and answering 400 with the field errors when
+/// it is invalid, so the handler only gets valid input.
+pub struct ValidateJson<T, F> {
+    handler: F,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, F> ValidateJson<T, F> {
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State, T, F, Fut> Endpoint<State> for ValidateJson<T, F>
+    where
+        State: Clone + Send + Sync + 'static,
+        T: DeserializeOwned + Validate + 'static,
+        F: 'static + Send + Sync + Fn(Request<State>, T) -> Fut,
+        Fut: Future<Output = HttpResult<Response>> + 'static,
+{
+    async fn call(&self, mut req: Request<State>) -> HttpResult<Response> {
+        let value = check(req.body_json::<T>().await?)?;
+        (self.handler)(req, value).await
+    }
+}
+
+/// Like `ValidateJson`, for the query string.
+pub struct ValidateQuery<T, F> {
+    handler: F,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, F> ValidateQuery<T, F> {
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl<State, T, F, Fut> Endpoint<State> for ValidateQuery<T, F>
+    where
+        State: Clone + Send + Sync + 'static,
+        T: DeserializeOwned + Validate + 'static,
+        F: 'static + Send + Sync + Fn(Request<State>, T) -> Fut,
+        Fut: Future<Output = HttpResult<Response>> + 'static,
+{
+    async fn call(&self, req: Request<State>) -> HttpResult<Response> {
+        let value = check(req.query::<T>()?)?;
+        (self.handler)(req, value).await
+    }
+}
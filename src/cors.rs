@@ -0,0 +1,194 @@
+/// CORS settings shared by the server backends. The `Access-Control-Allow-Origin` value is
+/// the single request `Origin` when it is listed or matches a pattern; other origins allowed
+/// through `*` get a literal `*` and never `Access-Control-Allow-Credentials`.
+#[derive(Clone, Debug)]
+pub struct CorsConfig {
+    allow_origins: Vec<String>,
+    allow_methods: String,
+    allow_headers: Option<String>,
+    echo_request_headers: bool,
+    expose_headers: Option<String>,
+    allow_credentials: bool,
+    max_age: Option<u64>,
+}
+
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let (pattern_scheme, pattern_host) = match pattern.split_once("://") {
+        Some((scheme, host)) => (Some(scheme), host),
+        None => (None, pattern),
+    };
+    let domain = match pattern_host.strip_prefix('*') {
+        Some(domain) if domain.starts_with('.') => domain,
+        _ => return false,
+    };
+    let (scheme, host) = match origin.split_once("://") {
+        Some(v) => v,
+        None => return false,
+    };
+    if pattern_scheme.map(|v| !v.eq_ignore_ascii_case(scheme)).unwrap_or(false) {
+        return false;
+    }
+    host.len() > domain.len()
+        && host.to_ascii_lowercase().ends_with(domain.to_ascii_lowercase().as_str())
+}
+
+impl CorsConfig {
+    pub fn new(allow_origins: Vec<String>) -> Self {
+        Self {
+            allow_origins,
+            allow_methods: "GET, POST, PUT, DELETE, OPTIONS".to_string(),
+            allow_headers: None,
+            echo_request_headers: false,
+            expose_headers: None,
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+
+    pub fn allow_methods(mut self, methods: impl Into<String>) -> Self {
+        self.allow_methods = methods.into();
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: impl Into<String>) -> Self {
+        self.allow_headers = Some(headers.into());
+        self
+    }
+
+    /// Allow whatever `Access-Control-Request-Headers` a preflight asks for when no allow
+    /// headers are set, off by default.
+    pub fn echo_request_headers(mut self, echo: bool) -> Self {
+        self.echo_request_headers = echo;
+        self
+    }
+
+    pub fn expose_headers(mut self, headers: impl Into<String>) -> Self {
+        self.expose_headers = Some(headers.into());
+        self
+    }
+
+    pub fn allow_credentials(mut self, allow_credentials: bool) -> Self {
+        self.allow_credentials = allow_credentials;
+        self
+    }
+
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn get_allow_methods(&self) -> &str {
+        self.allow_methods.as_str()
+    }
+
+    fn allow_any(&self) -> bool {
+        self.allow_origins.iter().any(|v| v == "*")
+    }
+
+    fn is_listed(&self, origin: &str) -> bool {
+        self.allow_origins.iter().any(|v| v != "*" && (v == origin || origin_matches(v, origin)))
+    }
+
+    /// Origins are matched exactly, except patterns like `*.example.com` or
+    /// `https://*.example.com` which allow any subdomain, but not `example.com` itself.
+    pub fn is_allowed(&self, origin: &str) -> bool {
+        self.allow_any() || self.is_listed(origin)
+    }
+
+    /// Whether responses depend on the request `Origin` and need `Vary: Origin`.
+    pub fn vary_origin(&self) -> bool {
+        self.allow_origins.iter().any(|v| v != "*")
+    }
+
+    /// Headers for a simple or actual CORS request from an allowed `origin`. Credentials are
+    /// only allowed for listed origins, so `*` doesn't let any site make credentialed reads.
+    pub fn response_headers(&self, origin: &str) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if self.is_listed(origin) {
+            headers.push(("Access-Control-Allow-Origin", origin.to_string()));
+            if self.allow_credentials {
+                headers.push(("Access-Control-Allow-Credentials", "true".to_string()));
+            }
+        } else {
+            headers.push(("Access-Control-Allow-Origin", "*".to_string()));
+        }
+        if let Some(expose_headers) = self.expose_headers.as_ref() {
+            headers.push(("Access-Control-Expose-Headers", expose_headers.clone()));
+        }
+        headers
+    }
+
+    /// Headers answering a preflight request from an allowed `origin`. Without configured
+    /// allow headers only CORS-safelisted request headers are allowed, unless
+    /// `echo_request_headers` is on.
+    pub fn preflight_headers(&self, origin: &str, request_headers: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut headers = self.response_headers(origin);
+        headers.retain(|(name, _)| *name != "Access-Control-Expose-Headers");
+        headers.push(("Access-Control-Allow-Methods", self.allow_methods.clone()));
+        let request_headers = request_headers.filter(|_| self.echo_request_headers).map(|v| v.to_string());
+        if let Some(allow_headers) = self.allow_headers.clone().or(request_headers) {
+            headers.push(("Access-Control-Allow-Headers", allow_headers));
+        }
+        if let Some(max_age) = self.max_age {
+            headers.push(("Access-Control-Max-Age", max_age.to_string()));
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CorsConfig;
+
+    #[test]
+    fn test_wildcard_origin() {
+        let config = CorsConfig::new(vec!["*.example.com".to_string(), "https://*.tenant.io".to_string(), "http://a.com".to_string()]);
+        assert!(config.is_allowed("https://app.example.com"));
+        assert!(config.is_allowed("http://a.b.example.com"));
+        assert!(!config.is_allowed("https://example.com"));
+        assert!(!config.is_allowed("https://evilexample.com"));
+        assert!(!config.is_allowed("https://app.example.com.evil.com"));
+
+        assert!(config.is_allowed("https://x.tenant.io"));
+        assert!(!config.is_allowed("http://x.tenant.io"));
+
+        assert!(config.is_allowed("http://a.com"));
+        assert!(!config.is_allowed("https://a.com"));
+        assert!(!config.is_allowed("http://b.com"));
+
+        let headers = config.response_headers("https://app.example.com");
+        assert!(headers.contains(&("Access-Control-Allow-Origin", "https://app.example.com".to_string())));
+        assert!(config.vary_origin());
+    }
+
+    #[test]
+    fn test_preflight_request_headers() {
+        let config = CorsConfig::new(vec!["http://a.com".to_string()]);
+        let headers = config.preflight_headers("http://a.com", Some("x-token"));
+        assert!(!headers.iter().any(|(name, _)| *name == "Access-Control-Allow-Headers"));
+
+        let config = config.echo_request_headers(true);
+        let headers = config.preflight_headers("http://a.com", Some("x-token"));
+        assert!(headers.contains(&("Access-Control-Allow-Headers", "x-token".to_string())));
+
+        let config = config.allow_headers("content-type");
+        let headers = config.preflight_headers("http://a.com", Some("x-token"));
+        assert!(headers.contains(&("Access-Control-Allow-Headers", "content-type".to_string())));
+    }
+
+    #[test]
+    fn test_any_origin_credentials() {
+        let config = CorsConfig::new(vec!["*".to_string()]).allow_credentials(true);
+        let headers = config.response_headers("http://evil.com");
+        assert_eq!(headers, vec![("Access-Control-Allow-Origin", "*".to_string())]);
+        assert!(!config.vary_origin());
+
+        let config = CorsConfig::new(vec!["*".to_string(), "http://a.com".to_string()]).allow_credentials(true);
+        let headers = config.response_headers("http://a.com");
+        assert!(headers.contains(&("Access-Control-Allow-Origin", "http://a.com".to_string())));
+        assert!(headers.contains(&("Access-Control-Allow-Credentials", "true".to_string())));
+        let headers = config.response_headers("http://evil.com");
+        assert_eq!(headers, vec![("Access-Control-Allow-Origin", "*".to_string())]);
+        assert!(config.vary_origin());
+    }
+}
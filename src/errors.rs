@@ -1,7 +1,9 @@
 pub(crate) use sfo_result::err as http_err;
 pub(crate) use sfo_result::into_err as into_http_err;
 
-#[repr(u16)]
+/// Error codes, converted to the envelope `err` value with `u16::from`. Since 0.6 the codes
+/// carrying a status can't be cast with `as u16` anymore, and the numbering starts at
+/// `Failed = 1` as `0` means success in the envelope.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ErrorCode {
     Failed,
@@ -11,6 +13,172 @@ pub enum ErrorCode {
     ServerError,
     NotFound,
     IOError,
+    Timeout,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    HttpStatus(u16),
+    Custom(u16, u16),
 }
+impl From<ErrorCode> for u16 {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Failed => 1,
+            ErrorCode::InvalidData => 2,
+            ErrorCode::ConnectFailed => 3,
+            ErrorCode::InvalidParam => 4,
+            ErrorCode::ServerError => 5,
+            ErrorCode::NotFound => 6,
+            ErrorCode::IOError => 7,
+            ErrorCode::Timeout => 8,
+            ErrorCode::PayloadTooLarge => 9,
+            ErrorCode::UnsupportedMediaType => 10,
+            ErrorCode::HttpStatus(status) => 1000 + status,
+            ErrorCode::Custom(code, _) => code,
+        }
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            2 => ErrorCode::InvalidData,
+            3 => ErrorCode::ConnectFailed,
+            4 => ErrorCode::InvalidParam,
+            5 => ErrorCode::ServerError,
+            6 => ErrorCode::NotFound,
+            7 => ErrorCode::IOError,
+            8 => ErrorCode::Timeout,
+            9 => ErrorCode::PayloadTooLarge,
+            10 => ErrorCode::UnsupportedMediaType,
+            1000..=1999 => ErrorCode::HttpStatus(code - 1000),
+            _ => ErrorCode::Failed,
+        }
+    }
+}
+
+thread_local! {
+    static EXPOSE_ERROR_DETAIL: std::cell::Cell<bool> = const { std::cell::Cell::new(true) };
+}
+
+/// Whether error messages may be sent to the client, as configured on the server handling the
+/// current request. `true` outside of a request, e.g. in a spawned task.
+pub(crate) fn expose_error_detail() -> bool {
+    EXPOSE_ERROR_DETAIL.with(|v| v.get())
+}
+
+// runs `fut` with `expose_error_detail()` returning `expose` while it is polled
+pub(crate) async fn with_error_detail<F: std::future::Future>(expose: bool, fut: F) -> F::Output {
+    let mut fut = Box::pin(fut);
+    futures_util::future::poll_fn(move |cx| {
+        let prev = EXPOSE_ERROR_DETAIL.with(|v| v.replace(expose));
+        let ret = fut.as_mut().poll(cx);
+        EXPOSE_ERROR_DETAIL.with(|v| v.set(prev));
+        ret
+    }).await
+}
+
 pub type HttpError = sfo_result::Error<ErrorCode>;
 pub type HttpResult<T> = sfo_result::Result<T, ErrorCode>;
+
+/// The request url and response status behind an `ErrorCode::HttpStatus` client error.
+#[derive(Debug, Clone)]
+pub struct HttpStatusError {
+    url: String,
+    status: u16,
+}
+
+impl HttpStatusError {
+    pub(crate) fn new(url: &str, status: u16) -> Self {
+        Self {
+            url: url.to_string(),
+            status,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "http status error! url={}, status={}", self.url, self.status)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// The failing url and status of an error returned by the client for an error status.
+pub fn http_status_error(err: &HttpError) -> Option<&HttpStatusError> {
+    std::error::Error::source(err)?.downcast_ref::<HttpStatusError>()
+}
+
+/// An error code that can be sent in a response envelope, with the http status used for it.
+pub trait ResponseCode: std::fmt::Debug + Copy + Sync + Send + 'static + Into<u16> {
+    fn status(&self) -> u16 {
+        500
+    }
+}
+
+impl ResponseCode for ErrorCode {
+    fn status(&self) -> u16 {
+        match self {
+            ErrorCode::NotFound => 404,
+            ErrorCode::InvalidParam | ErrorCode::InvalidData => 400,
+            ErrorCode::PayloadTooLarge => 413,
+            ErrorCode::UnsupportedMediaType => 415,
+            ErrorCode::HttpStatus(status) => *status,
+            ErrorCode::Custom(_, status) => *status,
+            _ => 500,
+        }
+    }
+}
+
+/// Converts an error with a user defined code into a `HttpError` keeping its code and status.
+pub fn into_http_error<C: ResponseCode>(err: sfo_result::Error<C>) -> HttpError {
+    let code = err.code();
+    let msg = if err.msg().is_empty() {
+        format!("{:?}", code)
+    } else {
+        err.msg().to_string()
+    };
+    HttpError::new(ErrorCode::Custom(code.into(), code.status()), msg)
+}
+
+const JSON_ERROR_CONTEXT: usize = 24;
+
+/// Describes a json parse error of `body` with the byte offset it was detected at and up to
+/// `JSON_ERROR_CONTEXT` bytes of input on each side of it.
+pub(crate) fn json_error_msg(body: &[u8], err: &serde_json::Error) -> String {
+    let line_start: usize = body.split(|v| *v == b'\n').take(err.line().saturating_sub(1)).map(|line| line.len() + 1).sum();
+    let offset = (line_start + err.column().saturating_sub(1)).min(body.len());
+    let start = offset.saturating_sub(JSON_ERROR_CONTEXT);
+    let end = (offset + JSON_ERROR_CONTEXT).min(body.len());
+    let snippet = format!("{}{}{}",
+                          if start > 0 { "..." } else { "" },
+                          String::from_utf8_lossy(&body[start..end]),
+                          if end < body.len() { "..." } else { "" });
+    format!("{} (byte {}, near {:?})", err, offset, snippet)
+}
+
+#[cfg(test)]
+mod test {
+    use super::ErrorCode;
+
+    #[test]
+    fn test_error_code_value() {
+        let codes = [ErrorCode::Failed, ErrorCode::InvalidData, ErrorCode::ConnectFailed, ErrorCode::InvalidParam,
+            ErrorCode::ServerError, ErrorCode::NotFound, ErrorCode::IOError, ErrorCode::Timeout,
+            ErrorCode::PayloadTooLarge, ErrorCode::UnsupportedMediaType, ErrorCode::HttpStatus(503)];
+        let values: Vec<u16> = codes.iter().map(|v| u16::from(*v)).collect();
+        assert_eq!(values, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 1503]);
+        for code in codes {
+            assert_eq!(ErrorCode::from(u16::from(code)), code);
+        }
+        assert_eq!(u16::from(ErrorCode::Custom(2001, 409)), 2001);
+    }
+}
@@ -0,0 +1,37 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Joins the request `path` below `dir`, resolving `.` and `..`. Returns `None` when the result
+/// would leave `dir`.
+pub(crate) fn resolve_path(dir: &Path, path: &str) -> Option<PathBuf> {
+    let mut file_path = dir.to_path_buf();
+    for p in Path::new(path.trim_start_matches('/')) {
+        if p == OsStr::new(".") {
+            continue;
+        } else if p == OsStr::new("..") {
+            file_path.pop();
+        } else {
+            file_path.push(p);
+        }
+    }
+    if file_path.starts_with(dir) {
+        Some(file_path)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::resolve_path;
+
+    #[test]
+    fn test_resolve_path() {
+        let dir = Path::new("/srv/static");
+        assert_eq!(resolve_path(dir, "/css/./app.css").unwrap(), Path::new("/srv/static/css/app.css"));
+        assert_eq!(resolve_path(dir, "css/../index.html").unwrap(), Path::new("/srv/static/index.html"));
+        assert!(resolve_path(dir, "../secret").is_none());
+        assert!(resolve_path(dir, "css/../../../etc/passwd").is_none());
+    }
+}
@@ -0,0 +1,402 @@
+use std::net::{IpAddr, SocketAddr};
+
+struct MediaRange<'a> {
+    ty: &'a str,
+    sub_ty: &'a str,
+    q: f32,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(item: &'a str) -> Option<Self> {
+        let mut parts = item.split(';');
+        let media = parts.next()?.trim();
+        let (ty, sub_ty) = media.split_once('/')?;
+        let mut q = 1.0;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = value.trim().parse::<f32>().ok()?.clamp(0.0, 1.0);
+                }
+            }
+        }
+        Some(Self {
+            ty: ty.trim(),
+            sub_ty: sub_ty.trim(),
+            q,
+        })
+    }
+
+    // 0 means no match, higher values are more specific
+    fn specificity(&self, mime: &str) -> u8 {
+        let (ty, sub_ty) = match mime.split(';').next().unwrap_or("").split_once('/') {
+            Some(v) => v,
+            None => return 0,
+        };
+        if self.ty == "*" && self.sub_ty == "*" {
+            1
+        } else if self.ty.eq_ignore_ascii_case(ty.trim()) && self.sub_ty == "*" {
+            2
+        } else if self.ty.eq_ignore_ascii_case(ty.trim()) && self.sub_ty.eq_ignore_ascii_case(sub_ty.trim()) {
+            3
+        } else {
+            0
+        }
+    }
+}
+
+fn quality(ranges: &[MediaRange], mime: &str) -> f32 {
+    let mut best = 0;
+    let mut q = 0.0;
+    for range in ranges.iter() {
+        let specificity = range.specificity(mime);
+        if specificity > best {
+            best = specificity;
+            q = range.q;
+        }
+    }
+    q
+}
+
+/// Returns whether `mime` is acceptable according to the `Accept` header value.
+/// A missing header accepts everything.
+pub fn accepts(accept: Option<&str>, mime: &str) -> bool {
+    match accept {
+        Some(accept) => {
+            let ranges: Vec<_> = accept.split(',').filter_map(MediaRange::parse).collect();
+            quality(&ranges, mime) > 0.0
+        }
+        None => true,
+    }
+}
+
+/// Picks the offered content type with the highest q-value in the `Accept` header value.
+/// On equal q-values the first offered type wins.
+pub fn preferred_content_type(accept: Option<&str>, offered: &[&str]) -> Option<String> {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return offered.first().map(|v| v.to_string()),
+    };
+    let ranges: Vec<_> = accept.split(',').filter_map(MediaRange::parse).collect();
+    let mut preferred = None;
+    let mut preferred_q = 0.0;
+    for mime in offered.iter() {
+        let q = quality(&ranges, mime);
+        if q > preferred_q {
+            preferred_q = q;
+            preferred = Some(mime.to_string());
+        }
+    }
+    preferred
+}
+
+/// Picks the offered content coding with the highest q-value in the `Accept-Encoding` header
+/// value, `*` matching any coding not listed. Returns `None` when the identity coding should be used.
+pub fn preferred_encoding(accept_encoding: Option<&str>, offered: &[&str]) -> Option<String> {
+    let codings: Vec<(&str, f32)> = accept_encoding?.split(',').filter_map(|item| {
+        let mut parts = item.split(';');
+        let coding = parts.next()?.trim();
+        let mut q = 1.0;
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = value.trim().parse::<f32>().ok()?.clamp(0.0, 1.0);
+                }
+            }
+        }
+        Some((coding, q))
+    }).collect();
+    let mut preferred = None;
+    let mut preferred_q = 0.0;
+    for encoding in offered.iter() {
+        let q = codings.iter().find(|(coding, _)| coding.eq_ignore_ascii_case(encoding))
+            .or(codings.iter().find(|(coding, _)| *coding == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0);
+        if q > preferred_q {
+            preferred_q = q;
+            preferred = Some(encoding.to_string());
+        }
+    }
+    preferred
+}
+
+/// Parses an address as found in X-Forwarded-For/Forwarded: `1.2.3.4`, `1.2.3.4:80`, `::1`, `[::1]:80`.
+pub fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(ip) = value.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    value.strip_prefix('[')
+        .and_then(|v| v.split(']').next())
+        .and_then(|v| v.parse::<IpAddr>().ok())
+}
+
+/// Resolves the client address from an X-Forwarded-For chain, trusting only the
+/// `trusted_proxies` hops closest to the server (the peer being the first of them).
+/// Falls back to the peer when a trusted hop holds something that isn't an address.
+pub fn forwarded_client_ip(x_forwarded_for: Option<&str>, peer: Option<IpAddr>, trusted_proxies: usize) -> Option<IpAddr> {
+    if trusted_proxies == 0 {
+        return peer;
+    }
+    let hops: Vec<&str> = x_forwarded_for.map(|v| v.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()).collect()).unwrap_or_default();
+    if hops.is_empty() {
+        return peer;
+    }
+    match parse_ip(hops[hops.len().saturating_sub(trusted_proxies)]) {
+        Some(ip) => Some(ip),
+        None => peer,
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ForwardedElement {
+    pub by: Option<String>,
+    pub forwarded_for: Option<String>,
+    pub host: Option<String>,
+    pub proto: Option<String>,
+}
+
+// Splits on `sep` outside of quoted strings
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+    let mut list = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if in_quotes && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == sep && !in_quotes {
+            list.push(&value[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    list.push(&value[start..]);
+    list
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(quoted) => {
+            let mut unquoted = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' {
+                    if let Some(c) = chars.next() {
+                        unquoted.push(c);
+                    }
+                } else {
+                    unquoted.push(c);
+                }
+            }
+            unquoted
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Parses a `Forwarded` header value (RFC 7239) into its comma separated elements.
+pub fn parse_forwarded(value: &str) -> Vec<ForwardedElement> {
+    let mut elements = Vec::new();
+    for element in split_unquoted(value, ',') {
+        let mut forwarded = ForwardedElement::default();
+        for pair in split_unquoted(element, ';') {
+            let (name, value) = match pair.split_once('=') {
+                Some(v) => v,
+                None => continue,
+            };
+            let value = Some(unquote(value));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "by" => forwarded.by = value,
+                "for" => forwarded.forwarded_for = value,
+                "host" => forwarded.host = value,
+                "proto" => forwarded.proto = value.map(|v| v.to_ascii_lowercase()),
+                _ => {}
+            }
+        }
+        if forwarded != ForwardedElement::default() {
+            elements.push(forwarded);
+        }
+    }
+    elements
+}
+
+/// Returns the protocol of the original request from `Forwarded` or `X-Forwarded-Proto`.
+pub fn forwarded_proto(forwarded: Option<&str>, x_forwarded_proto: Option<&str>) -> Option<String> {
+    if let Some(proto) = forwarded.and_then(|v| parse_forwarded(v).into_iter().find_map(|v| v.proto)) {
+        return Some(proto);
+    }
+    x_forwarded_proto.and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+}
+
+/// Whether an `If-None-Match` value matches `etag`, using the weak comparison of RFC 9110.
+pub fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let etag = etag.trim().trim_start_matches("W/");
+    match if_none_match {
+        Some(value) => value.split(',').map(|v| v.trim()).any(|v| v == "*" || v.trim_start_matches("W/") == etag),
+        None => false,
+    }
+}
+
+/// Merges `name` into an existing `Vary` value, keeping it unchanged when it is `*` or
+/// already lists `name`.
+pub fn append_vary(vary: Option<&str>, name: &str) -> String {
+    let items: Vec<&str> = vary.unwrap_or("").split(',').map(|v| v.trim()).filter(|v| !v.is_empty()).collect();
+    if items.iter().any(|v| *v == "*" || v.eq_ignore_ascii_case(name)) {
+        items.join(", ")
+    } else if items.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}, {}", items.join(", "), name)
+    }
+}
+
+/// Returns the token of an `Authorization: Bearer <token>` header. The scheme is matched case-insensitively.
+pub fn bearer_token(authorization: Option<&str>) -> Option<String> {
+    let (scheme, token) = authorization?.trim().split_once(' ')?;
+    let token = token.trim();
+    if scheme.eq_ignore_ascii_case("bearer") && !token.is_empty() {
+        Some(token.to_string())
+    } else {
+        None
+    }
+}
+
+/// Returns the user and password of an `Authorization: Basic <base64(user:password)>` header.
+pub fn basic_credentials(authorization: Option<&str>) -> Option<(String, String)> {
+    use base64::Engine;
+    let (scheme, credentials) = authorization?.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let credentials = base64::engine::general_purpose::STANDARD.decode(credentials.trim()).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (user, password) = credentials.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_forwarded() {
+        let list = parse_forwarded("for=\"[2001:db8:cafe::17]:4711\";proto=HTTPS;host=example.com, for=192.0.2.43");
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].forwarded_for.as_deref(), Some("[2001:db8:cafe::17]:4711"));
+        assert_eq!(list[0].proto.as_deref(), Some("https"));
+        assert_eq!(list[0].host.as_deref(), Some("example.com"));
+        assert_eq!(list[1].forwarded_for.as_deref(), Some("192.0.2.43"));
+        assert_eq!(parse_ip(list[0].forwarded_for.as_deref().unwrap()), Some("2001:db8:cafe::17".parse().unwrap()));
+
+        let list = parse_forwarded("For=\"a,b;c\\\"d\"; by=unknown");
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].forwarded_for.as_deref(), Some("a,b;c\"d"));
+        assert_eq!(list[0].by.as_deref(), Some("unknown"));
+    }
+
+    #[test]
+    fn test_forwarded_proto() {
+        assert_eq!(forwarded_proto(Some("for=1.2.3.4;proto=https"), Some("http")), Some("https".to_string()));
+        assert_eq!(forwarded_proto(Some("for=1.2.3.4"), Some("HTTPS, http")), Some("https".to_string()));
+        assert_eq!(forwarded_proto(None, None), None);
+    }
+
+    #[test]
+    fn test_parse_ip() {
+        assert_eq!(parse_ip("1.2.3.4"), Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(parse_ip(" 1.2.3.4:8080 "), Some("1.2.3.4".parse().unwrap()));
+        assert_eq!(parse_ip("2001:db8::1"), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_ip("[2001:db8::1]:443"), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_ip("\"[2001:db8::1]\""), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(parse_ip("unknown"), None);
+    }
+
+    #[test]
+    fn test_forwarded_client_ip() {
+        let peer: Option<IpAddr> = Some("10.0.0.1".parse().unwrap());
+        // no trusted proxy, the header is ignored
+        assert_eq!(forwarded_client_ip(Some("1.1.1.1"), peer, 0), peer);
+        // one trusted proxy, a spoofed leftmost entry is ignored
+        assert_eq!(forwarded_client_ip(Some("6.6.6.6, 2.2.2.2"), peer, 1), Some("2.2.2.2".parse().unwrap()));
+        // two trusted proxies
+        assert_eq!(forwarded_client_ip(Some("6.6.6.6, 2.2.2.2, 10.0.0.2"), peer, 2), Some("2.2.2.2".parse().unwrap()));
+        // chain shorter than the trusted depth
+        assert_eq!(forwarded_client_ip(Some("2.2.2.2"), peer, 3), Some("2.2.2.2".parse().unwrap()));
+        assert_eq!(forwarded_client_ip(Some("[2001:db8::1]:1234"), peer, 1), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(forwarded_client_ip(Some("garbage"), peer, 1), peer);
+        assert_eq!(forwarded_client_ip(None, peer, 1), peer);
+    }
+
+    #[test]
+    fn test_preferred_content_type() {
+        let accept = Some("text/csv;q=0.9, application/json");
+        assert_eq!(preferred_content_type(accept, &["text/csv", "application/json"]), Some("application/json".to_string()));
+        assert_eq!(preferred_content_type(accept, &["text/csv", "text/html"]), Some("text/csv".to_string()));
+        assert_eq!(preferred_content_type(accept, &["text/html"]), None);
+        assert_eq!(preferred_content_type(None, &["text/csv", "application/json"]), Some("text/csv".to_string()));
+        assert_eq!(preferred_content_type(Some("text/*;q=0.5, */*;q=0.1"), &["application/json", "text/csv"]), Some("text/csv".to_string()));
+    }
+
+    #[test]
+    fn test_preferred_encoding() {
+        assert_eq!(preferred_encoding(None, &["gzip", "deflate"]), None);
+        assert_eq!(preferred_encoding(Some("gzip, deflate, br"), &["gzip", "deflate"]), Some("gzip".to_string()));
+        assert_eq!(preferred_encoding(Some("gzip;q=0.5, deflate"), &["gzip", "deflate"]), Some("deflate".to_string()));
+        assert_eq!(preferred_encoding(Some("gzip;q=0, *"), &["gzip", "deflate"]), Some("deflate".to_string()));
+        assert_eq!(preferred_encoding(Some("br, identity"), &["gzip", "deflate"]), None);
+    }
+
+    #[test]
+    fn test_accepts() {
+        let accept = Some("text/csv;q=0.9, application/json, image/*;q=0");
+        assert!(accepts(accept, "text/csv"));
+        assert!(accepts(accept, "application/json; charset=utf-8"));
+        assert!(!accepts(accept, "image/png"));
+        assert!(!accepts(accept, "text/html"));
+        assert!(accepts(None, "text/html"));
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token(Some("Bearer abc.def")), Some("abc.def".to_string()));
+        assert_eq!(bearer_token(Some("  bearer   abc.def ")), Some("abc.def".to_string()));
+        assert_eq!(bearer_token(Some("Basic YWxpY2U6cHdk")), None);
+        assert_eq!(bearer_token(Some("Bearer ")), None);
+        assert_eq!(bearer_token(None), None);
+    }
+
+    #[test]
+    fn test_append_vary() {
+        assert_eq!(append_vary(None, "Origin"), "Origin");
+        assert_eq!(append_vary(Some("Origin"), "Accept-Encoding"), "Origin, Accept-Encoding");
+        assert_eq!(append_vary(Some("Origin, accept-encoding"), "Accept-Encoding"), "Origin, accept-encoding");
+        assert_eq!(append_vary(Some("*"), "Origin"), "*");
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches(Some("\"a\", \"b\""), "\"b\""));
+        assert!(etag_matches(Some("W/\"b\""), "\"b\""));
+        assert!(etag_matches(Some("*"), "\"b\""));
+        assert!(!etag_matches(Some("\"a\""), "\"b\""));
+        assert!(!etag_matches(None, "\"b\""));
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        assert_eq!(basic_credentials(Some("Basic YWxpY2U6cDp3ZA==")), Some(("alice".to_string(), "p:wd".to_string())));
+        assert_eq!(basic_credentials(Some("Bearer YWxpY2U6cDp3ZA==")), None);
+        assert_eq!(basic_credentials(Some("Basic !!!")), None);
+        assert_eq!(basic_credentials(None), None);
+    }
+}
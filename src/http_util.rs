@@ -7,17 +7,86 @@ mod json {
 pub use reqwest::*;
 use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
+use std::future::Future;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use ::json::JsonValue;
 use serde::{Deserialize, Serialize};
 use crate::errors::{HttpError, ErrorCode, HttpResult};
 use reqwest::dns::Resolve;
 use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue};
 
+const DEFAULT_TIMEOUT_ENV: &str = "SFO_HTTP_TIMEOUT";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+static DEFAULT_TIMEOUT_MS: AtomicU64 = AtomicU64::new(0);
+
+// Overall timeout used by the free http_* helpers. Defaults to SFO_HTTP_TIMEOUT (seconds) or 60s.
+pub fn default_timeout() -> Duration {
+    let timeout = DEFAULT_TIMEOUT_MS.load(Ordering::Relaxed);
+    if timeout != 0 {
+        return Duration::from_millis(timeout);
+    }
+    let timeout = env_timeout(std::env::var(DEFAULT_TIMEOUT_ENV).ok().as_deref());
+    DEFAULT_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::Relaxed);
+    timeout
+}
+
+// SFO_HTTP_TIMEOUT in seconds, 60s when unset, zero or not a number
+fn env_timeout(value: Option<&str>) -> Duration {
+    value.and_then(|v| v.trim().parse::<u64>().ok())
+        .filter(|v| *v != 0)
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TIMEOUT)
+}
+
+pub fn set_default_timeout(timeout: Duration) {
+    DEFAULT_TIMEOUT_MS.store((timeout.as_millis() as u64).max(1), Ordering::Relaxed);
+}
+
+fn default_client() -> reqwest::Client {
+    client_with_timeout(default_timeout())
+}
+
+pub(crate) fn client_with_timeout(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder().no_proxy().timeout(timeout).build().unwrap()
+}
+
+fn send_error_code(err: &reqwest::Error) -> ErrorCode {
+    if err.is_timeout() {
+        ErrorCode::Timeout
+    } else {
+        ErrorCode::ConnectFailed
+    }
+}
+
+// Time left before `deadline`, or a timeout error when it has already passed.
+fn remaining_time(url: &str, deadline: tokio::time::Instant) -> HttpResult<Duration> {
+    let now = tokio::time::Instant::now();
+    if deadline <= now {
+        let msg = format!("deadline exceeded before request! url={}", url);
+        log::error!("{}", msg.as_str());
+        return Err(HttpError::new(ErrorCode::Timeout, msg));
+    }
+    Ok(deadline - now)
+}
+
+fn check_status(url: &str, resp: Response) -> HttpResult<Response> {
+    let status = resp.status();
+    if status.is_client_error() || status.is_server_error() {
+        let context = crate::errors::HttpStatusError::new(url, status.as_u16());
+        let msg = context.to_string();
+        log::error!("{}", msg.as_str());
+        Err(HttpError::new2(ErrorCode::HttpStatus(status.as_u16()), msg, Box::new(context)))
+    } else {
+        Ok(resp)
+    }
+}
+
 pub async fn http_post_request(url: &str, param: Vec<u8>, content_type: Option<&str>) -> HttpResult<(Vec<u8>, Option<String>)> {
-    let mut request_builder = reqwest::Client::builder().no_proxy().build().unwrap().post(url);
+    let mut request_builder = default_client().post(url);
     if content_type.is_some() {
         request_builder = request_builder.header(CONTENT_TYPE, content_type.unwrap());
     }
@@ -25,7 +94,7 @@ pub async fn http_post_request(url: &str, param: Vec<u8>, content_type: Option<&
     let mut resp = request_builder.body(param).send().await.map_err(|err| {
         let msg = format!("http connect error! host={}, err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })?;
 
     let header = resp.headers().get(CONTENT_TYPE);
@@ -47,7 +116,7 @@ pub async fn http_post_request(url: &str, param: Vec<u8>, content_type: Option<&
 }
 
 pub async fn http_post_request2<T: for<'de> Deserialize<'de>>(url: &str, param: Vec<u8>, content_type: Option<&str>) -> HttpResult<T> {
-    let mut request_builder = reqwest::Client::builder().no_proxy().build().unwrap().post(url);
+    let mut request_builder = default_client().post(url);
     if content_type.is_some() {
         request_builder = request_builder.header(CONTENT_TYPE, content_type.unwrap());
     }
@@ -55,9 +124,10 @@ pub async fn http_post_request2<T: for<'de> Deserialize<'de>>(url: &str, param:
     let mut resp = request_builder.body(param).send().await.map_err(|err| {
         let msg = format!("http connect error! host={}, err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })?;
 
+    let resp = check_status(url, resp)?;
     let data = resp.json().await.map_err(|err| {
         let msg = format!("recv body error! err={}", err);
         log::error!("{}", msg.as_str());
@@ -67,12 +137,13 @@ pub async fn http_post_request2<T: for<'de> Deserialize<'de>>(url: &str, param:
 }
 
 pub async fn http_post_request3<T: for<'de> Deserialize<'de>, P: Serialize>(url: &str, param: &P) -> HttpResult<T> {
-    let mut resp = reqwest::Client::builder().no_proxy().build().unwrap().post(url).json(param).send().await.map_err(|err| {
+    let mut resp = default_client().post(url).json(param).send().await.map_err(|err| {
         let msg = format!("http connect error! host={}, err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })?;
 
+    let resp = check_status(url, resp)?;
     resp.json().await.map_err(|err| {
         let msg = format!("recv error! err={}", err);
         log::error!("{}", msg.as_str());
@@ -81,12 +152,13 @@ pub async fn http_post_request3<T: for<'de> Deserialize<'de>, P: Serialize>(url:
 }
 
 pub async fn http_get_request2<T: for<'de> Deserialize<'de>>(url: &str) -> HttpResult<T> {
-    let resp = reqwest::Client::builder().no_proxy().build().unwrap().get(url).send().await.map_err(|err| {
+    let resp = default_client().get(url).send().await.map_err(|err| {
         let msg = format!("http connect error! host={}, err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })?;
 
+    let resp = check_status(url, resp)?;
     resp.json().await.map_err(|err| {
         let msg = format!("recv error! err={}", err);
         log::error!("{}", msg.as_str());
@@ -96,10 +168,14 @@ pub async fn http_get_request2<T: for<'de> Deserialize<'de>>(url: &str) -> HttpR
 
 
 pub async fn http_get_request(url: &str) -> HttpResult<(Vec<u8>, Option<String>)> {
-    let resp = reqwest::Client::builder().no_proxy().build().unwrap().get(url).send().await.map_err(|err| {
+    get_request(default_client(), url).await
+}
+
+pub(crate) async fn get_request(client: reqwest::Client, url: &str) -> HttpResult<(Vec<u8>, Option<String>)> {
+    let resp = client.get(url).send().await.map_err(|err| {
         let msg = format!("http connect error! host={}, err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })?;
 
     let header = resp.headers().get(CONTENT_TYPE);
@@ -121,31 +197,30 @@ pub async fn http_get_request(url: &str) -> HttpResult<(Vec<u8>, Option<String>)
 }
 
 pub async fn http_get_request3(url: &str) -> HttpResult<Response> {
-    reqwest::Client::builder().no_proxy().build().unwrap().get(url).send().await.map_err(|err| {
+    default_client().get(url).send().await.map_err(|err| {
         let msg = format!("http connect error! host={}, err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })
 }
 
 pub async fn http_request(req: Request) -> HttpResult<Response> {
     let url = req.url().to_string();
-    reqwest::Client::builder().no_proxy().build().unwrap().execute(req).await.map_err(|err| {
+    default_client().execute(req).await.map_err(|err| {
         let msg = format!("http connect error! url={} err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })
 }
 
 pub async fn http_post_json(url: &str, param: JsonValue) -> HttpResult<JsonValue> {
-    let resp = reqwest::Client::builder().no_proxy().build().unwrap()
-        .post(url)
+    let resp = default_client().post(url)
         .header(CONTENT_TYPE, "application/json")
         .body(param.to_string())
         .send().await.map_err(|err| {
         let msg = format!("http connect error! url={} err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })?;
 
     let resp_str = resp.text().await.map_err(|err| {
@@ -162,15 +237,16 @@ pub async fn http_post_json(url: &str, param: JsonValue) -> HttpResult<JsonValue
 
 
 pub async fn http_post_json2<T: for<'de> Deserialize<'de>>(url: &str, param: JsonValue) -> HttpResult<T> {
-    let resp = reqwest::Client::builder().no_proxy().build().unwrap().post(url)
+    let resp = default_client().post(url)
         .header(CONTENT_TYPE, "application/json")
         .body(param.to_string())
         .send().await.map_err(|err| {
         let msg = format!("http connect error! url={} err={}", url, err);
         log::error!("{}", msg.as_str());
-        HttpError::new(ErrorCode::ConnectFailed, msg)
+        HttpError::new(send_error_code(&err), msg)
     })?;
 
+    let resp = check_status(url, resp)?;
     resp.json().await.map_err(|err| {
         let msg = format!("recv error! err={}", err);
         log::error!("{}", msg.as_str());
@@ -178,10 +254,242 @@ pub async fn http_post_json2<T: for<'de> Deserialize<'de>>(url: &str, param: Jso
     })
 }
 
+#[derive(Deserialize)]
+struct JsonResult<T> {
+    err: u16,
+    msg: String,
+    result: Option<T>,
+}
+
+impl<T: for<'de> Deserialize<'de>> JsonResult<T> {
+    fn into_result(self) -> HttpResult<T> {
+        if self.err != 0 {
+            return Err(HttpError::new(ErrorCode::from(self.err), self.msg));
+        }
+        match self.result {
+            Some(result) => Ok(result),
+            None => serde_json::from_value(serde_json::Value::Null).map_err(|err| {
+                let msg = format!("missing result! err={}", err);
+                log::error!("{}", msg.as_str());
+                HttpError::new(ErrorCode::InvalidData, msg)
+            }),
+        }
+    }
+}
+
+/// Converts a client side error into the caller's code type through its numeric value.
+fn into_typed_err<C>(err: HttpError) -> sfo_result::Error<C>
+    where C: From<u16> + std::fmt::Debug + Copy + Send + Sync + 'static {
+    sfo_result::Error::new(C::from(err.code().into()), err.msg().to_string())
+}
+
+#[derive(Clone, Default)]
+pub struct RequestOptions {
+    timeout: Option<Duration>,
+    proxy: Option<Proxy>,
+    no_proxy: bool,
+    resolve: Vec<(String, SocketAddr)>,
+}
+
+impl RequestOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn set_proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self.no_proxy = false;
+        self
+    }
+
+    pub fn set_no_proxy(mut self) -> Self {
+        self.proxy = None;
+        self.no_proxy = true;
+        self
+    }
+
+    pub fn resolve(mut self, domain: &str, addr: SocketAddr) -> Self {
+        self.resolve.push((domain.to_string(), addr));
+        self
+    }
+
+    fn client_key(&self) -> Option<String> {
+        if self.proxy.is_none() && !self.no_proxy && self.resolve.is_empty() {
+            None
+        } else {
+            Some(format!("{:?}|{}|{:?}", self.proxy, self.no_proxy, self.resolve))
+        }
+    }
+}
+
+#[cfg(feature = "client_metrics")]
+const METRICS_SAMPLE_SIZE: usize = 1024;
+
+#[cfg(feature = "client_metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct ClientMetricsSnapshot {
+    pub requests: u64,
+    pub errors: u64,
+    pub p50: Duration,
+    pub p99: Duration,
+}
+
+/// Request counters and the latencies of the last requests sent by a `HttpClient`.
+/// Failed sends and 5xx responses count as errors.
+#[cfg(feature = "client_metrics")]
+#[derive(Default)]
+struct ClientMetrics {
+    requests: AtomicU64,
+    errors: AtomicU64,
+    latencies: Mutex<std::collections::VecDeque<Duration>>,
+}
+
+#[cfg(feature = "client_metrics")]
+impl ClientMetrics {
+    fn record(&self, latency: Duration, is_error: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if is_error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let mut latencies = self.latencies.lock().unwrap();
+        if latencies.len() == METRICS_SAMPLE_SIZE {
+            latencies.pop_front();
+        }
+        latencies.push_back(latency);
+    }
+
+    fn snapshot(&self) -> ClientMetricsSnapshot {
+        let mut latencies: Vec<Duration> = self.latencies.lock().unwrap().iter().cloned().collect();
+        latencies.sort();
+        let percentile = |p: usize| {
+            if latencies.is_empty() {
+                Duration::ZERO
+            } else {
+                latencies[(latencies.len() - 1) * p / 100]
+            }
+        };
+        ClientMetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            p50: percentile(50),
+            p99: percentile(99),
+        }
+    }
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+/// Per host circuit breaker: opens after `failure_threshold` consecutive failures,
+/// rejects calls during `cooldown`, then lets a single probe through.
+#[derive(Clone)]
+struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    hosts: Arc<Mutex<HashMap<String, BreakerState>>>,
+}
+
+impl CircuitBreaker {
+    fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn acquire(&self, host: &str) -> HttpResult<BreakerPermit> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_string()).or_default();
+        let mut probe = false;
+        if let Some(open_until) = state.open_until {
+            if Instant::now() < open_until || state.probing {
+                let msg = format!("circuit open! host={}", host);
+                log::warn!("{}", msg.as_str());
+                return Err(HttpError::new(ErrorCode::ConnectFailed, msg));
+            }
+            state.probing = true;
+            probe = true;
+        }
+        Ok(BreakerPermit {
+            breaker: self.clone(),
+            host: host.to_string(),
+            probe,
+            released: false,
+        })
+    }
+
+    fn release(&self, host: &str, success: bool) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_string()).or_default();
+        if success {
+            *state = BreakerState::default();
+        } else {
+            state.failures += 1;
+            if state.probing || state.failures >= self.failure_threshold {
+                state.open_until = Some(Instant::now() + self.cooldown);
+                state.probing = false;
+            }
+        }
+    }
+}
+
+/// A call let through by the breaker. A probe dropped before `release`, e.g. because the
+/// request future was cancelled, counts as failed so the half-open state doesn't stick.
+struct BreakerPermit {
+    breaker: CircuitBreaker,
+    host: String,
+    probe: bool,
+    released: bool,
+}
+
+impl BreakerPermit {
+    fn release(mut self, success: bool) {
+        self.released = true;
+        self.breaker.release(self.host.as_str(), success);
+    }
+}
+
+impl Drop for BreakerPermit {
+    fn drop(&mut self) {
+        if !self.released && self.probe {
+            self.breaker.release(self.host.as_str(), false);
+        }
+    }
+}
+
+fn url_host(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => format!("{}:{}", url.host_str().unwrap_or(""), url.port_or_known_default().unwrap_or(0)),
+        Err(_) => url.to_string(),
+    }
+}
+
+// The settings of a `HttpClient`, replayed on a fresh builder for the clients of `send_with`.
+type ClientConfig = Arc<dyn Fn(ClientBuilder) -> ClientBuilder + Send + Sync>;
+
+// Clients built for distinct `RequestOptions`, all dropped when the cache is full.
+const MAX_OPTION_CLIENTS: usize = 32;
+
 #[derive(Clone)]
 pub struct HttpClient {
     client: reqwest::Client,
+    config: ClientConfig,
     base_url: Option<String>,
+    headers: HeaderMap,
+    option_clients: Arc<Mutex<HashMap<String, reqwest::Client>>>,
+    circuit_breaker: Option<CircuitBreaker>,
+    #[cfg(feature = "client_metrics")]
+    metrics: Arc<ClientMetrics>,
 }
 
 impl Debug for HttpClient {
@@ -192,12 +500,13 @@ impl Debug for HttpClient {
 
 impl HttpClient {
     pub fn new(max_connections: usize, base_url: Option<&str>) -> Self {
-        let client = reqwest::ClientBuilder::new()
-            .connect_timeout(Duration::from_secs(30))
-            .http2_keep_alive_while_idle(true)
-            .pool_max_idle_per_host(max_connections)
-            .no_proxy()
-            .build().unwrap();
+        let config: ClientConfig = Arc::new(move |builder| {
+            builder.connect_timeout(Duration::from_secs(30))
+                .http2_keep_alive_while_idle(true)
+                .pool_max_idle_per_host(max_connections)
+                .no_proxy()
+        });
+        let client = config(reqwest::ClientBuilder::new()).build().unwrap();
 
         let base_url = if base_url.is_some() {
             let base_url = base_url.unwrap();
@@ -213,19 +522,26 @@ impl HttpClient {
 
         Self {
             client,
+            config,
             base_url,
+            headers: HeaderMap::new(),
+            option_clients: Arc::new(Mutex::new(HashMap::new())),
+            circuit_breaker: None,
+            #[cfg(feature = "client_metrics")]
+            metrics: Arc::new(ClientMetrics::default()),
         }
     }
 
     pub fn new_with_no_cert_verify(max_connections: usize, base_url: Option<&str>) -> Self {
-        let client = reqwest::ClientBuilder::new()
-            .connect_timeout(Duration::from_secs(30))
-            .http2_keep_alive_while_idle(true)
-            .use_rustls_tls()
-            .pool_max_idle_per_host(max_connections)
-            .danger_accept_invalid_certs(true)
-            .no_proxy()
-            .build().unwrap();
+        let config: ClientConfig = Arc::new(move |builder| {
+            builder.connect_timeout(Duration::from_secs(30))
+                .http2_keep_alive_while_idle(true)
+                .use_rustls_tls()
+                .pool_max_idle_per_host(max_connections)
+                .danger_accept_invalid_certs(true)
+                .no_proxy()
+        });
+        let client = config(reqwest::ClientBuilder::new()).build().unwrap();
 
         let base_url = if base_url.is_some() {
             let base_url = base_url.unwrap();
@@ -241,38 +557,76 @@ impl HttpClient {
 
         Self {
             client,
+            config,
             base_url,
+            headers: HeaderMap::new(),
+            option_clients: Arc::new(Mutex::new(HashMap::new())),
+            circuit_breaker: None,
+            #[cfg(feature = "client_metrics")]
+            metrics: Arc::new(ClientMetrics::default()),
         }
     }
 
+    /// Resolves `uri` against the base url like `Url::join`: absolute urls are used as is,
+    /// `/path` replaces the base path and `path` is appended to it.
     fn get_url(&self, uri: &str) -> String {
-        if self.base_url.is_some() {
-            let base_url = self.base_url.as_ref().unwrap().as_str();
-            if base_url.ends_with("/") {
-                if uri.starts_with("/") {
-                    format!("{}{}", base_url, &uri[1..])
-                } else {
-                    format!("{}{}", base_url, uri)
-                }
-            } else {
-                if uri.starts_with("/") {
-                    format!("{}{}", base_url, uri)
-                } else {
-                    format!("{}/{}", base_url, uri)
+        match self.base_url.as_ref() {
+            Some(base_url) => match Url::parse(base_url).and_then(|base| base.join(uri)) {
+                Ok(url) => url.to_string(),
+                Err(e) => {
+                    log::error!("join url {} {} failed.{}", base_url, uri, e);
+                    format!("{}{}", base_url, uri.trim_start_matches('/'))
                 }
-            }
-        } else {
-            uri.to_string()
+            },
+            None => uri.to_string(),
         }
     }
 
-    pub async fn get_json<T: for<'de> Deserialize<'de>>(&self, uri: &str) -> HttpResult<T> {
-        let mut resp = self.client.get(self.get_url(uri).as_str()).send().await.map_err(|err| {
-            let msg = format!("http connect error! url={}, err={}", self.get_url(uri), err);
+    async fn timed_send(&self, url: &str, fut: impl Future<Output = reqwest::Result<Response>>) -> HttpResult<Response> {
+        let permit = match self.circuit_breaker.as_ref() {
+            Some(breaker) => Some(breaker.acquire(url_host(url).as_str())?),
+            None => None,
+        };
+        #[cfg(feature = "client_metrics")]
+        let start = std::time::Instant::now();
+        let ret = fut.await.map_err(|err| {
+            let msg = format!("http connect error! url={}, err={}", url, err);
+            log::error!("{}", msg.as_str());
+            HttpError::new(send_error_code(&err), msg)
+        });
+        let is_error = ret.as_ref().map(|resp| resp.status().is_server_error()).unwrap_or(true);
+        #[cfg(feature = "client_metrics")]
+        self.metrics.record(start.elapsed(), is_error);
+        if let Some(permit) = permit {
+            permit.release(!is_error);
+        }
+        ret
+    }
+
+    #[cfg(feature = "client_metrics")]
+    pub fn metrics(&self) -> ClientMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Resolves the base url and opens a pooled connection to it with a `HEAD` request, so the
+    /// first real request doesn't pay for DNS and TLS. Any response status counts as success.
+    pub async fn warmup(&self) -> HttpResult<()> {
+        let base_url = self.base_url.as_ref().ok_or_else(|| {
+            log::error!("warmup without base url");
+            HttpError::new(ErrorCode::InvalidParam, "base url is not set".to_string())
+        })?;
+        self.client.head(base_url.as_str()).send().await.map_err(|err| {
+            let msg = format!("warmup error! url={}, err={}", base_url, err);
             log::error!("{}", msg.as_str());
-            HttpError::new(ErrorCode::ConnectFailed, msg)
+            HttpError::new(send_error_code(&err), msg)
         })?;
+        Ok(())
+    }
+
+    pub async fn get_json<T: for<'de> Deserialize<'de>>(&self, uri: &str) -> HttpResult<T> {
+        let mut resp = self.timed_send(self.get_url(uri).as_str(), self.client.get(self.get_url(uri).as_str()).send()).await?;
 
+        let resp = check_status(self.get_url(uri).as_str(), resp)?;
         resp.json().await.map_err(|err| {
             let msg = format!("recv error! err={}", err);
             log::error!("{}", msg.as_str());
@@ -280,12 +634,33 @@ impl HttpClient {
         })
     }
 
-    pub async fn get(&self, uri: &str) -> HttpResult<(Vec<u8>, Option<String>)> {
-        let mut resp = self.client.get(self.get_url(uri).as_str()).send().await.map_err(|err| {
-            let msg = format!("http connect error! url={}, err={}", self.get_url(uri), err);
+    pub async fn get_result<T: for<'de> Deserialize<'de>>(&self, uri: &str) -> HttpResult<T> {
+        let result: JsonResult<T> = self.get_json(uri).await?;
+        result.into_result()
+    }
+
+    /// Like `get_json`, but the whole request, body included, must finish before `deadline`.
+    /// Fails with `ErrorCode::Timeout` without sending anything if the deadline has passed.
+    pub async fn get_json_within<T: for<'de> Deserialize<'de>>(&self, uri: &str, deadline: tokio::time::Instant) -> HttpResult<T> {
+        let url = self.get_url(uri);
+        let timeout = remaining_time(url.as_str(), deadline)?;
+        let resp = self.timed_send(url.as_str(), self.client.get(url.as_str()).timeout(timeout).send()).await?;
+
+        let resp = check_status(url.as_str(), resp)?;
+        resp.json().await.map_err(|err| {
+            let msg = format!("recv error! err={}", err);
             log::error!("{}", msg.as_str());
-            HttpError::new(ErrorCode::ConnectFailed, msg)
-        })?;
+            HttpError::new(if err.is_timeout() { ErrorCode::Timeout } else { ErrorCode::InvalidData }, msg)
+        })
+    }
+
+    pub async fn get_result_within<T: for<'de> Deserialize<'de>>(&self, uri: &str, deadline: tokio::time::Instant) -> HttpResult<T> {
+        let result: JsonResult<T> = self.get_json_within(uri, deadline).await?;
+        result.into_result()
+    }
+
+    pub async fn get(&self, uri: &str) -> HttpResult<(Vec<u8>, Option<String>)> {
+        let mut resp = self.timed_send(self.get_url(uri).as_str(), self.client.get(self.get_url(uri).as_str()).send()).await?;
 
         let header = resp.headers().get(CONTENT_TYPE);
         let header = if header.is_some() {
@@ -306,12 +681,38 @@ impl HttpClient {
     }
 
     pub async fn post_json<T: for<'de> Deserialize<'de>, P: Serialize>(&self, uri: &str, param: &P) -> HttpResult<T> {
-        let mut resp = self.client.post(self.get_url(uri)).json(param).send().await.map_err(|err| {
-            let msg = format!("http connect error! url={}, err={}", self.get_url(uri), err);
+        let mut resp = self.timed_send(self.get_url(uri).as_str(), self.client.post(self.get_url(uri)).json(param).send()).await?;
+
+        let resp = check_status(self.get_url(uri).as_str(), resp)?;
+        resp.json().await.map_err(|err| {
+            let msg = format!("recv error! err={}", err);
             log::error!("{}", msg.as_str());
-            HttpError::new(ErrorCode::ConnectFailed, msg)
-        })?;
+            HttpError::new(ErrorCode::InvalidData, msg)
+        })
+    }
+
+    pub async fn post_json_within<T: for<'de> Deserialize<'de>, P: Serialize>(&self, uri: &str, param: &P, deadline: tokio::time::Instant) -> HttpResult<T> {
+        let url = self.get_url(uri);
+        let timeout = remaining_time(url.as_str(), deadline)?;
+        let resp = self.timed_send(url.as_str(), self.client.post(url.as_str()).json(param).timeout(timeout).send()).await?;
 
+        let resp = check_status(url.as_str(), resp)?;
+        resp.json().await.map_err(|err| {
+            let msg = format!("recv error! err={}", err);
+            log::error!("{}", msg.as_str());
+            HttpError::new(if err.is_timeout() { ErrorCode::Timeout } else { ErrorCode::InvalidData }, msg)
+        })
+    }
+
+    pub async fn post_result_within<T: for<'de> Deserialize<'de>, P: Serialize>(&self, uri: &str, param: &P, deadline: tokio::time::Instant) -> HttpResult<T> {
+        let result: JsonResult<T> = self.post_json_within(uri, param, deadline).await?;
+        result.into_result()
+    }
+
+    pub async fn post_form<T: for<'de> Deserialize<'de>, P: Serialize>(&self, uri: &str, param: &P) -> HttpResult<T> {
+        let mut resp = self.timed_send(self.get_url(uri).as_str(), self.client.post(self.get_url(uri)).form(param).send()).await?;
+
+        let resp = check_status(self.get_url(uri).as_str(), resp)?;
         resp.json().await.map_err(|err| {
             let msg = format!("recv error! err={}", err);
             log::error!("{}", msg.as_str());
@@ -319,17 +720,53 @@ impl HttpClient {
         })
     }
 
+    pub async fn post_result<T: for<'de> Deserialize<'de>, P: Serialize>(&self, uri: &str, param: &P) -> HttpResult<T> {
+        let result: JsonResult<T> = self.post_json(uri, param).await?;
+        result.into_result()
+    }
+
+    /// Like `post_result`, but a non-zero `err` of the envelope is returned as the caller's error
+    /// code `C`, also when it comes with an error status. Transport errors are converted from
+    /// their `ErrorCode` value.
+    pub async fn post_result_typed<T, C, P>(&self, uri: &str, param: &P) -> sfo_result::Result<T, C>
+        where
+            T: for<'de> Deserialize<'de>,
+            C: From<u16> + std::fmt::Debug + Copy + Send + Sync + 'static,
+            P: Serialize, {
+        let url = self.get_url(uri);
+        let resp = self.timed_send(url.as_str(), self.client.post(url.as_str()).json(param).send()).await.map_err(into_typed_err)?;
+        let status = resp.status();
+        let body = resp.bytes().await.map_err(|err| {
+            let msg = format!("recv body error! err={}", err);
+            log::error!("{}", msg.as_str());
+            into_typed_err(HttpError::new(ErrorCode::InvalidData, msg))
+        })?;
+        let result: JsonResult<T> = match serde_json::from_slice(&body) {
+            Ok(result) => result,
+            Err(_) if status.is_client_error() || status.is_server_error() => {
+                let msg = format!("http status error! url={}, status={}", url, status);
+                log::error!("{}", msg.as_str());
+                return Err(into_typed_err(HttpError::new(ErrorCode::HttpStatus(status.as_u16()), msg)));
+            }
+            Err(err) => {
+                let msg = format!("recv error! err={}", err);
+                log::error!("{}", msg.as_str());
+                return Err(into_typed_err(HttpError::new(ErrorCode::InvalidData, msg)));
+            }
+        };
+        if result.err != 0 {
+            return Err(sfo_result::Error::new(C::from(result.err), result.msg));
+        }
+        result.into_result().map_err(into_typed_err)
+    }
+
     pub async fn post(&self, uri: &str, param: Vec<u8>, content_type: Option<&str>) -> HttpResult<(Vec<u8>, Option<String>)> {
         let mut request_builder = self.client.post(self.get_url(uri));
         if content_type.is_some() {
             request_builder = request_builder.header(CONTENT_TYPE, content_type.unwrap());
         }
         // req.set_body(param);
-        let mut resp = request_builder.body(param).send().await.map_err(|err| {
-            let msg = format!("http connect error! host={}, err={}", self.get_url(uri), err);
-            log::error!("{}", msg.as_str());
-            HttpError::new(ErrorCode::ConnectFailed, msg)
-        })?;
+        let mut resp = self.timed_send(self.get_url(uri).as_str(), request_builder.body(param).send()).await?;
 
         let header = resp.headers().get(CONTENT_TYPE);
         let header = if header.is_some() {
@@ -350,33 +787,87 @@ impl HttpClient {
         Ok((data.to_vec(), header))
     }
 
+    /// Starts a raw reqwest request against the base url, sent with the client's default headers.
+    pub fn request(&self, method: Method, uri: &str) -> RequestBuilder {
+        self.client.request(method, self.get_url(uri))
+    }
+
+    // The client for `options`: the settings of this client with the proxy and resolve
+    // overrides of `options` on top.
+    fn option_client(&self, options: &RequestOptions) -> HttpResult<reqwest::Client> {
+        let key = match options.client_key() {
+            Some(key) => key,
+            None => return Ok(self.client.clone()),
+        };
+        let mut option_clients = self.option_clients.lock().unwrap();
+        if let Some(client) = option_clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let mut builder = (self.config)(reqwest::ClientBuilder::new())
+            .default_headers(self.headers.clone());
+        if let Some(proxy) = options.proxy.as_ref() {
+            builder = builder.no_proxy().proxy(proxy.clone());
+        } else if options.no_proxy {
+            builder = builder.no_proxy();
+        }
+        for (domain, addr) in options.resolve.iter() {
+            builder = builder.resolve(domain.as_str(), *addr);
+        }
+        let client = builder.build().map_err(|err| {
+            let msg = format!("build client error! err={}", err);
+            log::error!("{}", msg.as_str());
+            HttpError::new(ErrorCode::InvalidParam, msg)
+        })?;
+        if option_clients.len() >= MAX_OPTION_CLIENTS {
+            option_clients.clear();
+        }
+        option_clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    /// Sends `req` with `options`. Proxy and resolve overrides use a client built from the
+    /// settings of this one, cached for up to 32 distinct options before the cache is reset.
+    pub async fn send_with(&self, mut req: Request, options: RequestOptions) -> HttpResult<Response> {
+        let client = self.option_client(&options)?;
+        if options.timeout.is_some() {
+            *req.timeout_mut() = options.timeout;
+        }
+        let url = req.url().to_string();
+        self.timed_send(url.as_str(), client.execute(req)).await
+    }
+
     pub async fn execute(&self, req: Request) -> HttpResult<Response> {
         let url = req.url().to_string();
-        self.client.execute(req).await.map_err(|err| {
-            let msg = format!("http connect error! url={} err={}", url, err);
-            log::error!("{}", msg.as_str());
-            HttpError::new(ErrorCode::ConnectFailed, msg)
-        })
+        self.timed_send(url.as_str(), self.client.execute(req)).await
     }
 }
 
 pub struct HttpClientBuilder {
     base_url: Option<String>,
-    builder: ClientBuilder,
+    config: ClientConfig,
     headers: HeaderMap,
+    circuit_breaker: Option<CircuitBreaker>,
 }
 
 impl Default for HttpClientBuilder {
     fn default() -> Self {
         Self {
             base_url: None,
-            builder: ClientBuilder::new(),
+            config: Arc::new(|builder| builder),
             headers: Default::default(),
+            circuit_breaker: None,
         }
     }
 }
 
 impl HttpClientBuilder {
+    fn configure(mut self, f: impl Fn(ClientBuilder) -> ClientBuilder + Send + Sync + 'static) -> Self {
+        let config = self.config.clone();
+        self.config = Arc::new(move |builder| f(config(builder)));
+        self
+    }
+
     pub fn set_base_url(mut self, base_url: &str) -> Self {
         let base_url = if base_url.ends_with("/") {
             base_url.to_string()
@@ -396,99 +887,266 @@ impl HttpClientBuilder {
         Ok(self)
     }
 
-    pub fn set_http_keep_alive(mut self, keep_alive: bool) -> Self {
-        self.builder = self.builder.http2_keep_alive_while_idle(keep_alive);
+    pub fn set_circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.circuit_breaker = Some(CircuitBreaker::new(failure_threshold, cooldown));
         self
     }
 
-    pub fn set_tcp_no_delay(mut self, no_delay: bool) -> Self {
-        self.builder = self.builder.tcp_nodelay(no_delay);
-        self
+    pub fn set_redirect_policy(self, policy: redirect::Policy) -> Self {
+        let policy = Arc::new(policy);
+        self.configure(move |builder| {
+            let policy = policy.clone();
+            builder.redirect(redirect::Policy::custom(move |attempt| policy.redirect(attempt)))
+        })
     }
 
-    pub fn set_timeout(mut self, timeout: Duration) -> Self {
-        self.builder = self.builder.connect_timeout(timeout);
-        self
+    /// Transparently decode gzip and brotli responses, enabled by default. When disabled the
+    /// raw body is returned together with its `Content-Encoding` header.
+    pub fn set_auto_decompress(self, auto_decompress: bool) -> Self {
+        self.configure(move |builder| builder.gzip(auto_decompress).brotli(auto_decompress))
     }
 
-    pub fn set_max_connections_per_host(mut self, max_connections_per_host: usize) -> Self {
-        self.builder = self.builder.pool_max_idle_per_host(max_connections_per_host);
-        self
+    pub fn set_http_keep_alive(self, keep_alive: bool) -> Self {
+        self.configure(move |builder| builder.http2_keep_alive_while_idle(keep_alive))
     }
 
-    pub fn set_verify_tls(mut self, verify_tls: bool) -> Self {
-        self.builder = self.builder.danger_accept_invalid_certs(!verify_tls);
-        self
+    /// Interval of the HTTP/2 ping frames, without it no pings are sent and `set_http_keep_alive`
+    /// has no effect.
+    pub fn set_http2_keep_alive_interval(self, interval: Duration) -> Self {
+        self.configure(move |builder| builder.http2_keep_alive_interval(interval))
+    }
+
+    /// Closes the connection when a ping is not acknowledged within `timeout`.
+    pub fn set_http2_keep_alive_timeout(self, timeout: Duration) -> Self {
+        self.configure(move |builder| builder.http2_keep_alive_timeout(timeout))
     }
 
-    pub fn set_auto_sys_proxy(mut self, proxy: bool) -> Self {
-        if !proxy {
-            self.builder = self.builder.no_proxy();
+    pub fn set_tcp_no_delay(self, no_delay: bool) -> Self {
+        self.configure(move |builder| builder.tcp_nodelay(no_delay))
+    }
+
+    pub fn set_timeout(self, timeout: Duration) -> Self {
+        self.configure(move |builder| builder.connect_timeout(timeout))
+    }
+
+    pub fn set_max_connections_per_host(self, max_connections_per_host: usize) -> Self {
+        self.configure(move |builder| builder.pool_max_idle_per_host(max_connections_per_host))
+    }
+
+    pub fn set_verify_tls(self, verify_tls: bool) -> Self {
+        self.configure(move |builder| builder.danger_accept_invalid_certs(!verify_tls))
+    }
+
+    pub fn set_auto_sys_proxy(self, proxy: bool) -> Self {
+        if proxy {
+            return self;
         }
-        self
+        self.configure(|builder| builder.no_proxy())
     }
 
-    pub fn add_root_certificate(mut self, cert: Certificate) -> Self {
-        self.builder = self.builder.add_root_certificate(cert);
-        self
+    pub fn add_root_certificate(self, cert: Certificate) -> Self {
+        self.configure(move |builder| builder.add_root_certificate(cert.clone()))
     }
 
-    pub fn tls_built_in_root_certs(mut self, tls_built_in_root_certs: bool) -> Self {
-        self.builder = self.builder.tls_built_in_root_certs(tls_built_in_root_certs);
-        self
+    pub fn tls_built_in_root_certs(self, tls_built_in_root_certs: bool) -> Self {
+        self.configure(move |builder| builder.tls_built_in_root_certs(tls_built_in_root_certs))
     }
 
-    pub fn identity(mut self, identity: Identity) -> Self {
-        self.builder = self.builder.identity(identity);
-        self
+    pub fn identity(self, identity: Identity) -> Self {
+        self.configure(move |builder| builder.identity(identity.clone()))
     }
 
-    pub fn tls_sni(mut self, tls_sni: bool) -> Self {
-        self.builder = self.builder.tls_sni(tls_sni);
-        self
+    pub fn tls_sni(self, tls_sni: bool) -> Self {
+        self.configure(move |builder| builder.tls_sni(tls_sni))
     }
 
-    pub fn min_tls_version(mut self, version: tls::Version) -> Self {
-        self.builder = self.builder.min_tls_version(version);
-        self
+    pub fn min_tls_version(self, version: tls::Version) -> Self {
+        self.configure(move |builder| builder.min_tls_version(version))
     }
 
-    pub fn max_tls_version(mut self, version: tls::Version) -> Self {
-        self.builder = self.builder.max_tls_version(version);
-        self
+    pub fn max_tls_version(self, version: tls::Version) -> Self {
+        self.configure(move |builder| builder.max_tls_version(version))
     }
 
-    pub fn https_only(mut self, enabled: bool) -> Self {
-        self.builder = self.builder.https_only(enabled);
-        self
+    pub fn https_only(self, enabled: bool) -> Self {
+        self.configure(move |builder| builder.https_only(enabled))
     }
 
-    pub fn resolve(mut self, domain: &str, addr: SocketAddr) -> Self {
-        self.builder = self.builder.resolve(domain, addr);
-        self
+    pub fn resolve(self, domain: &str, addr: SocketAddr) -> Self {
+        let domain = domain.to_string();
+        self.configure(move |builder| builder.resolve(domain.as_str(), addr))
     }
 
-    pub fn resolve_to_addrs(mut self, domain: &str, addrs: &[SocketAddr]) -> Self {
-        self.builder = self.builder.resolve_to_addrs(domain, addrs);
-        self
+    pub fn resolve_to_addrs(self, domain: &str, addrs: &[SocketAddr]) -> Self {
+        let domain = domain.to_string();
+        let addrs = addrs.to_vec();
+        self.configure(move |builder| builder.resolve_to_addrs(domain.as_str(), addrs.as_slice()))
     }
 
-    pub fn dns_resolver<R: Resolve + 'static>(mut self, resolver: Arc<R>) -> Self {
-        self.builder = self.builder.dns_resolver(resolver);
-        self
+    pub fn dns_resolver<R: Resolve + 'static>(self, resolver: Arc<R>) -> Self {
+        self.configure(move |builder| builder.dns_resolver(resolver.clone()))
     }
 
-    pub fn proxy(mut self, proxy: Proxy) -> Self {
-        self.builder = self.builder.proxy(proxy);
-        self
+    pub fn proxy(self, proxy: Proxy) -> Self {
+        self.configure(move |builder| builder.proxy(proxy.clone()))
     }
 
     pub fn build(mut self) -> HttpClient {
-        let mut config = self.builder.default_headers(self.headers);
+        let headers = self.headers.clone();
+        let builder = (self.config)(ClientBuilder::new()).default_headers(self.headers);
 
         HttpClient {
-            client: config.build().unwrap(),
+            client: builder.build().unwrap(),
+            config: self.config,
             base_url: self.base_url,
+            headers,
+            option_clients: Arc::new(Mutex::new(HashMap::new())),
+            circuit_breaker: self.circuit_breaker,
+            #[cfg(feature = "client_metrics")]
+            metrics: Arc::new(ClientMetrics::default()),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use super::HttpClient;
+    use crate::errors::ErrorCode;
+
+    #[test]
+    fn test_env_timeout() {
+        assert_eq!(super::env_timeout(None), Duration::from_secs(60));
+        assert_eq!(super::env_timeout(Some("5")), Duration::from_secs(5));
+        assert_eq!(super::env_timeout(Some(" 7 ")), Duration::from_secs(7));
+        assert_eq!(super::env_timeout(Some("0")), Duration::from_secs(60));
+        assert_eq!(super::env_timeout(Some("1.5")), Duration::from_secs(60));
+        assert_eq!(super::env_timeout(Some("abc")), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_set_default_timeout() {
+        // only raised, so concurrent tests using the default timeout aren't cut short
+        let prev = super::default_timeout();
+        super::set_default_timeout(prev + Duration::from_secs(30));
+        assert_eq!(super::default_timeout(), prev + Duration::from_secs(30));
+        super::set_default_timeout(prev);
+        assert_eq!(super::default_timeout(), prev);
+    }
+
+    // keep-alive server answering "ok" to every request, returns its address and the number of accepted connections
+    fn start_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                std::thread::spawn(move || {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 1024];
+                    loop {
+                        let n = match stream.read(&mut chunk) {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => n,
+                        };
+                        buf.extend_from_slice(&chunk[..n]);
+                        while let Some(pos) = buf.windows(4).position(|v| v == b"\r\n\r\n") {
+                            let is_head = buf.starts_with(b"HEAD ");
+                            buf.drain(..pos + 4);
+                            let body = if is_head { "" } else { "ok" };
+                            let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}", body);
+                            if stream.write_all(resp.as_bytes()).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        (format!("http://{}", addr), connections)
+    }
+
+    #[tokio::test]
+    async fn test_warmup() {
+        let (addr, connections) = start_server();
+        let client = HttpClient::new(1, Some(addr.as_str()));
+        client.warmup().await.unwrap();
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        let (body, _) = client.get("/hello").await.unwrap();
+        assert_eq!(body, b"ok");
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+        assert!(HttpClient::new(1, None).warmup().await.is_err());
+        assert!(HttpClient::new(1, Some("http://127.0.0.1:1")).warmup().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http2_keep_alive() {
+        let (addr, _) = start_server();
+        let client = super::HttpClientBuilder::default()
+            .set_base_url(addr.as_str())
+            .set_http_keep_alive(true)
+            .set_http2_keep_alive_interval(std::time::Duration::from_secs(30))
+            .set_http2_keep_alive_timeout(std::time::Duration::from_secs(10))
+            .build();
+        let (body, _) = client.get("/hello").await.unwrap();
+        assert_eq!(body, b"ok");
+    }
+
+    #[test]
+    fn test_circuit_breaker_cancelled_probe() {
+        let breaker = super::CircuitBreaker::new(1, std::time::Duration::from_millis(50));
+        breaker.acquire("h:80").unwrap().release(false);
+        assert!(breaker.acquire("h:80").is_err());
+
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        let probe = breaker.acquire("h:80").unwrap();
+        assert!(breaker.acquire("h:80").is_err());
+        drop(probe);
+        assert!(breaker.acquire("h:80").is_err());
+
+        std::thread::sleep(std::time::Duration::from_millis(60));
+        breaker.acquire("h:80").unwrap().release(true);
+        breaker.acquire("h:80").unwrap().release(true);
+    }
+
+    #[test]
+    fn test_get_url() {
+        let client = HttpClient::new(1, Some("http://h/api"));
+        assert_eq!(client.get_url("v1/x"), "http://h/api/v1/x");
+        assert_eq!(client.get_url("/v1/x"), "http://h/v1/x");
+        assert_eq!(client.get_url("../v1/x"), "http://h/v1/x");
+        assert_eq!(client.get_url("https://other/v1/x?a=1"), "https://other/v1/x?a=1");
+
+        let client = HttpClient::new(1, Some("http://h:8080/"));
+        assert_eq!(client.get_url("/v1/x"), "http://h:8080/v1/x");
+
+        let client = HttpClient::new(1, None);
+        assert_eq!(client.get_url("http://h/v1/x"), "http://h/v1/x");
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let (addr, connections) = start_server();
+        let client = HttpClient::new(1, Some(addr.as_str()));
+        let deadline = tokio::time::Instant::now() - std::time::Duration::from_millis(1);
+        let start = std::time::Instant::now();
+        let err = client.get_json_within::<String>("/hello", deadline).await.err().unwrap();
+        assert_eq!(err.code(), ErrorCode::Timeout);
+        let err = client.post_json_within::<String, _>("/hello", &"hi", deadline).await.err().unwrap();
+        assert_eq!(err.code(), ErrorCode::Timeout);
+        assert!(start.elapsed() < std::time::Duration::from_millis(100));
+        assert_eq!(connections.load(Ordering::SeqCst), 0);
+
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(5);
+        assert_eq!(client.get_json_within::<String>("/hello", deadline).await.err().unwrap().code(), ErrorCode::InvalidData);
+    }
+}
@@ -7,7 +7,14 @@ pub use tide_server as http_server;
 pub mod token_helper;
 #[cfg(feature = "tide")]
 pub mod tide_governor_middleware;
+#[cfg(feature = "tide")]
+pub mod tide_cors_middleware;
+#[cfg(feature = "tide")]
+pub mod tide_compression_middleware;
 pub mod http_util;
+pub mod header_util;
+pub mod cors;
+mod file_util;
 pub mod errors;
 #[cfg(feature = "actix-web")]
 pub mod actix_server;
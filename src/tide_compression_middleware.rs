@@ -0,0 +1,71 @@
+use std::io::Write;
+use tide::http::headers::{ACCEPT_ENCODING, CONTENT_ENCODING};
+use tide::{utils::async_trait, Middleware, Next, Request, Result};
+use crate::header_util::preferred_encoding;
+use crate::tide_server::append_vary;
+
+/// Compresses response bodies with gzip or deflate, whichever the request `Accept-Encoding`
+/// prefers. The body is buffered before compressing, so it is not meant for streamed responses.
+/// Bodies smaller than `min_size`, 1024 bytes by default, and already encoded responses are sent as is.
+#[derive(Clone, Debug)]
+pub struct TideCompressionMiddleware {
+    min_size: usize,
+}
+
+impl TideCompressionMiddleware {
+    pub fn new() -> Self {
+        Self {
+            min_size: 1024,
+        }
+    }
+
+    pub fn min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+}
+
+impl Default for TideCompressionMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn compress(encoding: &str, body: &[u8]) -> std::io::Result<Vec<u8>> {
+    if encoding == "gzip" {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body)?;
+        encoder.finish()
+    } else {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body)?;
+        encoder.finish()
+    }
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for TideCompressionMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> Result {
+        let accept_encoding = req.header(ACCEPT_ENCODING).map(|values| values.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(","));
+        let mut resp = next.run(req).await;
+        append_vary(&mut resp, "Accept-Encoding");
+
+        let encoding = match preferred_encoding(accept_encoding.as_deref(), &["gzip", "deflate"]) {
+            Some(encoding) => encoding,
+            None => return Ok(resp),
+        };
+        if resp.header(CONTENT_ENCODING).is_some() || resp.len().map(|len| len < self.min_size).unwrap_or(false) {
+            return Ok(resp);
+        }
+
+        let body = resp.take_body().into_bytes().await?;
+        if body.len() < self.min_size {
+            resp.set_body(body);
+            return Ok(resp);
+        }
+        let compressed = compress(encoding.as_str(), body.as_slice())?;
+        resp.set_body(compressed);
+        resp.insert_header(CONTENT_ENCODING, encoding);
+        Ok(resp)
+    }
+}
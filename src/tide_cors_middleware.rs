@@ -0,0 +1,160 @@
+use tide::http::headers::{ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN};
+use tide::http::Method;
+use tide::{utils::async_trait, Middleware, Next, Request, Response, Result, StatusCode};
+use crate::cors::CorsConfig;
+use crate::tide_server::append_vary;
+
+/// CORS middleware answering with the single request `Origin` when it is listed or matches
+/// a pattern, and with `*`, without credentials, for origins only allowed through `*`.
+/// Preflight requests are answered with 204, the same as the actix backend.
+#[derive(Clone, Debug)]
+pub struct TideCorsMiddleware {
+    config: CorsConfig,
+}
+
+impl TideCorsMiddleware {
+    pub fn new(allow_origins: Vec<String>) -> Self {
+        Self::with_config(CorsConfig::new(allow_origins))
+    }
+
+    pub fn with_config(config: CorsConfig) -> Self {
+        Self {
+            config,
+        }
+    }
+
+    pub fn allow_methods(mut self, methods: impl Into<String>) -> Self {
+        self.config = self.config.allow_methods(methods);
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: impl Into<String>) -> Self {
+        self.config = self.config.allow_headers(headers);
+        self
+    }
+
+    pub fn echo_request_headers(mut self, echo: bool) -> Self {
+        self.config = self.config.echo_request_headers(echo);
+        self
+    }
+
+    pub fn expose_headers(mut self, headers: impl Into<String>) -> Self {
+        self.config = self.config.expose_headers(headers);
+        self
+    }
+
+    pub fn allow_credentials(mut self, allow_credentials: bool) -> Self {
+        self.config = self.config.allow_credentials(allow_credentials);
+        self
+    }
+
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.config = self.config.max_age(max_age);
+        self
+    }
+}
+
+#[async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for TideCorsMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> Result {
+        let origin = match req.header(ORIGIN).map(|v| v.last().as_str().to_string()) {
+            Some(origin) => origin,
+            None => return Ok(next.run(req).await),
+        };
+
+        let headers = if !self.config.is_allowed(origin.as_str()) {
+            log::debug!("cors origin {} not allowed", origin);
+            None
+        } else if req.method() == Method::Options && req.header(ACCESS_CONTROL_REQUEST_METHOD).is_some() {
+            let request_headers = req.header(ACCESS_CONTROL_REQUEST_HEADERS).map(|v| v.as_str().to_string());
+            let mut resp = Response::new(StatusCode::NoContent);
+            for (name, value) in self.config.preflight_headers(origin.as_str(), request_headers.as_deref()) {
+                resp.insert_header(name, value);
+            }
+            if self.config.vary_origin() {
+                append_vary(&mut resp, "Origin");
+            }
+            return Ok(resp);
+        } else {
+            Some(self.config.response_headers(origin.as_str()))
+        };
+
+        let mut resp = next.run(req).await;
+        for (name, value) in headers.unwrap_or_default() {
+            resp.insert_header(name, value);
+        }
+        if self.config.vary_origin() {
+            append_vary(&mut resp, "Origin");
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tide::http::headers::{ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_ORIGIN, VARY};
+    use tide::http::{Method, Request, Response, Url};
+    use super::TideCorsMiddleware;
+
+    fn cors_app(cors: TideCorsMiddleware) -> tide::Server<()> {
+        let mut app = tide::new();
+        app.with(cors);
+        app.at("/cors").get(|_| async { Ok("ok") });
+        app.at("/encoded").get(|_| async {
+            let mut resp = tide::Response::new(200);
+            resp.insert_header("Vary", "Accept-Encoding");
+            Ok(resp)
+        });
+        app
+    }
+
+    async fn get(app: &tide::Server<()>, origin: &str) -> Response {
+        get_path(app, "/cors", origin).await
+    }
+
+    async fn get_path(app: &tide::Server<()>, path: &str, origin: &str) -> Response {
+        let mut req = Request::new(Method::Get, Url::parse("http://localhost").unwrap().join(path).unwrap());
+        req.insert_header("Origin", origin);
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_allowed_origin() {
+        let app = cors_app(TideCorsMiddleware::new(vec!["http://a.com".to_string(), "http://b.com".to_string()]));
+        let resp = get(&app, "http://b.com").await;
+        assert_eq!(resp[ACCESS_CONTROL_ALLOW_ORIGIN].as_str(), "http://b.com");
+        assert_eq!(resp[VARY].as_str(), "Origin");
+    }
+
+    #[async_std::test]
+    async fn test_disallowed_origin() {
+        let app = cors_app(TideCorsMiddleware::new(vec!["http://a.com".to_string()]));
+        let resp = get(&app, "http://evil.com").await;
+        assert!(resp.header(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[async_std::test]
+    async fn test_credentials() {
+        let app = cors_app(TideCorsMiddleware::new(vec!["http://a.com".to_string()]).allow_credentials(true));
+        let resp = get(&app, "http://a.com").await;
+        assert_eq!(resp[ACCESS_CONTROL_ALLOW_ORIGIN].as_str(), "http://a.com");
+        assert_eq!(resp[ACCESS_CONTROL_ALLOW_CREDENTIALS].as_str(), "true");
+
+        let app = cors_app(TideCorsMiddleware::new(vec!["*".to_string()]).allow_credentials(true));
+        let resp = get(&app, "http://a.com").await;
+        assert_eq!(resp[ACCESS_CONTROL_ALLOW_ORIGIN].as_str(), "*");
+        assert!(resp.header(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        let app = cors_app(TideCorsMiddleware::new(vec!["*".to_string()]));
+        let resp = get(&app, "http://a.com").await;
+        assert_eq!(resp[ACCESS_CONTROL_ALLOW_ORIGIN].as_str(), "*");
+        assert!(resp.header(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+    }
+
+    #[async_std::test]
+    async fn test_vary_accumulates() {
+        let app = cors_app(TideCorsMiddleware::new(vec!["http://a.com".to_string()]));
+        let resp = get_path(&app, "/encoded", "http://a.com").await;
+        assert_eq!(resp[VARY].as_str(), "Accept-Encoding, Origin");
+    }
+}
@@ -55,6 +55,16 @@ impl LimitKey for IPAddrKey {
     }
 }
 
+// `times` requests per `duration`. None when a cell would be replenished in less than a nanosecond,
+// or when the whole burst spans more than the u64 nanoseconds (~584 years) governor can represent.
+fn period_quota(duration: Duration, times: NonZeroU32) -> Option<Quota> {
+    let replenish_interval = duration.checked_div(times.get())?;
+    if u64::try_from(replenish_interval.as_nanos() * times.get() as u128).is_err() {
+        return None;
+    }
+    Some(Quota::with_period(replenish_interval)?.allow_burst(times))
+}
+
 #[derive(Debug, Clone)]
 pub struct TideGovernorMiddleware<Key: LimitKey> {
     limit_key: Key,
@@ -66,13 +76,9 @@ impl<Key: LimitKey> TideGovernorMiddleware<Key> {
         where
             T: TryInto<NonZeroU32> {
         let times= times.try_into().map_or_else(|_| None, |v: NonZeroU32| Some(v))?;
-        let replenish_interval_ns =
-            duration.as_nanos() / times.get() as u128;
         Some(Self {
             limit_key,
-            limiter: Arc::new(RateLimiter::<Key::KeyType, _, _>::keyed(Quota::with_period(
-                Duration::from_nanos(replenish_interval_ns as u64),
-            )?.allow_burst(times))),
+            limiter: Arc::new(RateLimiter::<Key::KeyType, _, _>::keyed(period_quota(duration, times)?)),
         })
     }
 
@@ -81,13 +87,9 @@ impl<Key: LimitKey> TideGovernorMiddleware<Key> {
         where
             T: TryInto<NonZeroU32> {
         let times= times.try_into().map_or_else(|_| None, |v: NonZeroU32| Some(v))?;
-        let replenish_interval_ns =
-            duration.as_nanos() / times.get() as u128;
         Some(Self {
             limit_key: Key::default(),
-            limiter: Arc::new(RateLimiter::<Key::KeyType, _, _>::keyed(Quota::with_period(
-                Duration::from_nanos(replenish_interval_ns as u64),
-            )?.allow_burst(times))),
+            limiter: Arc::new(RateLimiter::<Key::KeyType, _, _>::keyed(period_quota(duration, times)?)),
         })
     }
 
@@ -159,3 +161,28 @@ impl<State: Clone + Send + Sync + 'static, Key: LimitKey> Middleware<State> for
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{IPAddrKey, TideGovernorMiddleware};
+
+    #[test]
+    fn test_with_period() {
+        assert!(TideGovernorMiddleware::<IPAddrKey>::with_period(Duration::from_secs(1), 10).is_some());
+        assert!(TideGovernorMiddleware::<IPAddrKey>::with_period(Duration::from_secs(1), 0).is_none());
+        assert!(TideGovernorMiddleware::<IPAddrKey>::with_period(Duration::from_nanos(100), 1000).is_none());
+        assert!(TideGovernorMiddleware::<IPAddrKey>::with_period(Duration::ZERO, 1).is_none());
+        assert!(TideGovernorMiddleware::<IPAddrKey>::with_period(Duration::from_secs(u64::MAX), 1).is_none());
+        assert!(TideGovernorMiddleware::<IPAddrKey>::new(IPAddrKey::default(), Duration::from_secs(500 * 365 * 24 * 3600), 2).is_some());
+        assert!(TideGovernorMiddleware::<IPAddrKey>::new(IPAddrKey::default(), Duration::from_secs(1000 * 365 * 24 * 3600), 2).is_none());
+    }
+
+    #[test]
+    fn test_period_quota() {
+        let duration = Duration::from_secs(500 * 365 * 24 * 3600 + 1);
+        let quota = super::period_quota(duration, 2u32.try_into().unwrap()).unwrap();
+        assert_eq!(quota.replenish_interval(), duration / 2);
+        assert_eq!(quota.burst_size().get(), 2);
+    }
+}
@@ -1,15 +1,18 @@
 use std::fmt::Debug;
+use std::net::IpAddr;
 use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tide::http::headers::{COOKIE, HeaderValue};
-use tide::security::{CorsMiddleware, Origin};
+use tide::http::headers::{ACCEPT, AUTHORIZATION, COOKIE, FORWARDED, HeaderName, HeaderValue, VARY};
 pub use tide::*;
 use tide::http::Mime;
 #[cfg(feature = "openapi")]
 use utoipa::openapi::{OpenApi, PathItem};
-use crate::errors::{ErrorCode, http_err, HttpResult, into_http_err};
+use crate::errors::{ErrorCode, expose_error_detail, http_err, HttpResult, into_http_err, ResponseCode, with_error_detail};
+use crate::tide_cors_middleware::TideCorsMiddleware;
 #[cfg(feature = "openapi")]
 use crate::openapi::OpenApiServer;
 
@@ -24,6 +27,9 @@ pub struct HttpJsonResult<T>
 impl <T> HttpJsonResult<T>
 where T: Serialize
 {
+    /// An error keeps only its code when the server handling the current request doesn't
+    /// expose error details, see `HttpServer::set_expose_error_detail`. Outside of a request,
+    /// e.g. in a spawned task, the message is always sent.
     pub fn from<C: Debug + Copy + Sync + Send + 'static + Into<u16>>(ret: sfo_result::Result<T, C>) -> Self {
         match ret {
             Ok(data) => {
@@ -34,7 +40,12 @@ where T: Serialize
                 }
             },
             Err(err) => {
-                let msg = if err.msg().is_empty() {
+                let msg = if !expose_error_detail() {
+                    if !err.msg().is_empty() {
+                        ::log::error!("request failed {:?}: {}", err.code(), err.msg());
+                    }
+                    format!("{:?}", err.code())
+                } else if err.msg().is_empty() {
                     format!("{:?}", err.code())
                 } else {
                     err.msg().to_string()
@@ -49,10 +60,58 @@ where T: Serialize
     }
 
     pub fn to_response(&self) -> Response {
-        let mut resp = Response::new(StatusCode::Ok);
-        resp.set_content_type("application/json");
-        resp.set_body(serde_json::to_string(self).unwrap());
-        resp
+        self.to_response_with_status(StatusCode::Ok)
+    }
+
+    pub fn to_response_with_status(&self, status: StatusCode) -> Response {
+        match serde_json::to_string(self) {
+            Ok(body) => {
+                let mut resp = Response::new(status);
+                resp.set_content_type("application/json");
+                resp.set_body(body);
+                resp
+            }
+            Err(e) => {
+                ::log::error!("serialize result failed {}", e);
+                let mut resp = Response::new(StatusCode::InternalServerError);
+                resp.set_content_type("application/json");
+                resp.set_body(json!({
+                    "err": u16::from(ErrorCode::ServerError),
+                    "msg": "serialize result failed",
+                    "result": null,
+                }));
+                resp
+            }
+        }
+    }
+}
+
+fn error_status(code: impl ResponseCode) -> StatusCode {
+    StatusCode::try_from(code.status()).unwrap_or(StatusCode::InternalServerError)
+}
+
+/// Like `HttpJsonResult::from(ret).to_response()`, but answers success with `status`
+/// and errors with the status matching their error code.
+pub fn from_result_status<T, C>(status: StatusCode, ret: sfo_result::Result<T, C>) -> Response
+    where
+        T: Serialize,
+        C: ResponseCode {
+    let status = match &ret {
+        Ok(_) => status,
+        Err(err) => error_status(err.code()),
+    };
+    HttpJsonResult::from(ret).to_response_with_status(status)
+}
+
+// runs the rest of the request with `expose_error_detail()` set as configured on the server
+struct ErrorDetailMiddleware {
+    expose: Arc<AtomicBool>,
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ErrorDetailMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> Result {
+        Ok(with_error_detail(self.expose.load(Ordering::Relaxed), next.run(req)).await)
     }
 }
 
@@ -60,6 +119,7 @@ pub struct HttpServer<T> {
     app: Server<T>,
     server_addr: String,
     port: u16,
+    expose_error_detail: Arc<AtomicBool>,
     #[cfg(feature = "openapi")]
     api_doc: Option<OpenApi>,
     enable_api_doc: bool,
@@ -85,33 +145,72 @@ impl<T: Clone + Send + Sync + 'static> OpenApiServer for HttpServer<T> {
 }
 
 impl<T: Clone + Send + Sync + 'static> HttpServer<T> {
+    /// `allow_headers` are exposed as well, for compatibility. Use `with_cors_config` to set
+    /// expose headers independently, or a max age. Credentials are allowed for the origins in
+    /// `allow_origin` only; the default, any origin, is answered with `*` and no credentials.
     pub fn new(state: T, server_addr: String, port: u16, allow_origin: Option<Vec<String>>, allow_headers: Option<String>, ) -> Self {
-        let mut app = tide::with_state(state);
-
-        let mut cors = CorsMiddleware::new()
-            .allow_methods(
-                "GET, POST, PUT, DELETE, OPTIONS"
-                    .parse::<HeaderValue>()
-                    .unwrap(),
-            )
-            .allow_origin(Origin::from(allow_origin.unwrap_or(vec!["*".to_string()])))
+        let mut cors = crate::cors::CorsConfig::new(allow_origin.unwrap_or(vec!["*".to_string()]))
+            .allow_methods("GET, POST, PUT, DELETE, OPTIONS")
             .allow_credentials(true);
-        if allow_headers.is_some() {
-            cors = cors.allow_headers(allow_headers.as_ref().unwrap().as_str().parse::<HeaderValue>().unwrap())
-                .expose_headers(allow_headers.as_ref().unwrap().as_str().parse::<HeaderValue>().unwrap());
+        if let Some(allow_headers) = allow_headers {
+            cors = cors.allow_headers(allow_headers.as_str())
+                .expose_headers(allow_headers.as_str());
         }
-        app.with(cors);
+        Self::with_cors_config(state, server_addr, port, cors)
+    }
+
+    /// Like `new`, with the full CORS settings, including expose headers and max age, taken from
+    /// the `CorsConfig` shared with the actix backend.
+    pub fn with_cors_config(state: T, server_addr: String, port: u16, cors: crate::cors::CorsConfig) -> Self {
+        let mut app = tide::with_state(state);
+        let expose_error_detail = Arc::new(AtomicBool::new(true));
+        app.with(ErrorDetailMiddleware { expose: expose_error_detail.clone() });
+        app.with(TideCorsMiddleware::with_config(cors));
 
         Self {
             app,
             server_addr,
             port,
+            expose_error_detail,
             #[cfg(feature = "openapi")]
             api_doc: None,
             enable_api_doc: true,
         }
     }
 
+    /// Send error messages to clients, on by default. When off, `HttpJsonResult::from` errors
+    /// built while a request is handled only carry the error code, the message is logged instead.
+    /// Results built in a task spawned by an endpoint are not covered.
+    pub fn set_expose_error_detail(&mut self, expose: bool) {
+        self.expose_error_detail.store(expose, Ordering::Relaxed);
+    }
+
+    /// Add a header to every response that doesn't already set it.
+    pub fn add_global_response_header(&mut self, name: HeaderName, value: HeaderValue) {
+        self.app.with(tide::utils::After(move |mut resp: Response| {
+            if resp.header(&name).is_none() {
+                resp.insert_header(name.clone(), value.clone());
+            }
+            async move { Ok(resp) }
+        }));
+    }
+
+    /// Serve the files below `dir` at `path`. Unlike tide's `Route::serve_dir` the path is
+    /// resolved the same way as by the actix backend.
+    pub fn serve_dir(&mut self, path: &str, dir: impl AsRef<Path>) -> HttpResult<()> {
+        let dir = dir.as_ref().canonicalize()
+            .map_err(into_http_err!(ErrorCode::IOError, "serve_dir failed"))?;
+        self.app.at(format!("{}/*path", path.trim_end_matches('/')).as_str()).get(ServeDir { dir });
+        Ok(())
+    }
+
+    pub fn serve_file(&mut self, path: &str, file: impl AsRef<Path>) -> HttpResult<()> {
+        let file = file.as_ref().canonicalize()
+            .map_err(into_http_err!(ErrorCode::IOError, "path {} failed", file.as_ref().to_string_lossy()))?;
+        self.app.at(path).get(ServeFile { file });
+        Ok(())
+    }
+
     pub async fn run(mut self) -> HttpResult<()> {
         let addr = format!("{}:{}", self.server_addr, self.port);
         ::log::info!("start http server:{}", addr);
@@ -163,6 +262,46 @@ impl<T: Clone + Send + Sync + 'static> HttpServer<T> {
     }
 }
 
+async fn file_response(path: &Path) -> Result<Response> {
+    match Body::from_file(path).await {
+        Ok(body) => Ok(Response::builder(StatusCode::Ok).body(body).build()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            ::log::warn!("File not found: {:?}", path);
+            Ok(Response::new(StatusCode::NotFound))
+        }
+        Err(e) => Err(Error::from_str(StatusCode::InternalServerError, format!("read file failed {}", e))),
+    }
+}
+
+struct ServeDir {
+    dir: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl<T: Clone + Send + Sync + 'static> Endpoint<T> for ServeDir {
+    async fn call(&self, req: Request<T>) -> Result {
+        let path = req.param("path").unwrap_or("");
+        match crate::file_util::resolve_path(&self.dir, path) {
+            Some(file_path) => file_response(file_path.as_path()).await,
+            None => {
+                ::log::warn!("Unauthorized attempt to read: {:?}", path);
+                Ok(Response::new(StatusCode::Forbidden))
+            }
+        }
+    }
+}
+
+struct ServeFile {
+    file: PathBuf,
+}
+
+#[async_trait::async_trait]
+impl<T: Clone + Send + Sync + 'static> Endpoint<T> for ServeFile {
+    async fn call(&self, _req: Request<T>) -> Result {
+        file_response(self.file.as_path()).await
+    }
+}
+
 impl<T> Deref for HttpServer<T> {
     type Target = Server<T>;
 
@@ -177,10 +316,87 @@ impl<T> DerefMut for HttpServer<T> {
     }
 }
 
+/// Adds `name` to the `Vary` header of `resp`, keeping the values already listed.
+pub fn append_vary(resp: &mut Response, name: &str) {
+    let vary = resp.header(VARY).map(|values| values.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(","));
+    resp.insert_header(VARY, crate::header_util::append_vary(vary.as_deref(), name));
+}
+
+/// Trailers sent after a chunked request body, available once the body has been read.
+/// `None` when the request has no trailers. It can be called only once per request.
+pub async fn get_trailers<STATE>(req: &mut Request<STATE>) -> Option<tide::http::trailers::Trailers> {
+    let req: &mut tide::http::Request = req.as_mut();
+    if !req.has_trailers() {
+        return None;
+    }
+    req.recv_trailers().await
+}
+
+/// Like `Request::body_json`, but an empty body is rejected with a 400 saying JSON was expected
+/// instead of an opaque parse error.
+pub async fn get_body_json<STATE, T: serde::de::DeserializeOwned>(req: &mut Request<STATE>) -> Result<T> {
+    let body = req.body_bytes().await?;
+    if body.is_empty() {
+        return Err(Error::from_str(StatusCode::BadRequest, "empty request body, expected JSON"));
+    }
+    serde_json::from_slice(body.as_slice()).map_err(|e| Error::from_str(StatusCode::BadRequest, format!("parse data failed {}", crate::errors::json_error_msg(&body, &e))))
+}
+
 pub fn get_param<'a, STATE>(req: &'a Request<STATE>, name: &str) -> tide::Result<&'a str> {
     req.param(name)
 }
 
+fn header_value_list<STATE>(req: &Request<STATE>, name: HeaderName) -> Option<String> {
+    req.header(name).map(|values| values.iter().map(|v| v.as_str()).collect::<Vec<_>>().join(","))
+}
+
+pub fn accepts<STATE>(req: &Request<STATE>, mime: &str) -> bool {
+    let accept = header_value_list(req, ACCEPT);
+    crate::header_util::accepts(accept.as_deref(), mime)
+}
+
+pub fn get_preferred_content_type<STATE>(req: &Request<STATE>, offered: &[&str]) -> Option<String> {
+    let accept = header_value_list(req, ACCEPT);
+    crate::header_util::preferred_content_type(accept.as_deref(), offered)
+}
+
+pub fn get_bearer_token<STATE>(req: &Request<STATE>) -> Option<String> {
+    let authorization = req.header(AUTHORIZATION).map(|v| v.last().as_str());
+    crate::header_util::bearer_token(authorization)
+}
+
+pub fn get_client_ip<STATE>(req: &Request<STATE>, trusted_proxies: usize) -> Option<IpAddr> {
+    let x_forwarded_for = header_value_list(req, HeaderName::from_string("x-forwarded-for".to_string()).ok()?);
+    let peer = req.peer_addr().and_then(crate::header_util::parse_ip);
+    crate::header_util::forwarded_client_ip(x_forwarded_for.as_deref(), peer, trusted_proxies)
+}
+
+pub fn get_proto<STATE>(req: &Request<STATE>) -> Option<String> {
+    let forwarded = header_value_list(req, FORWARDED);
+    let x_forwarded_proto = header_value_list(req, HeaderName::from_string("x-forwarded-proto".to_string()).ok()?);
+    crate::header_util::forwarded_proto(forwarded.as_deref(), x_forwarded_proto.as_deref())
+}
+
+pub fn get_scheme<STATE>(req: &Request<STATE>) -> Option<String> {
+    get_proto(req).or_else(|| Some(req.url().scheme().to_string()))
+}
+
+pub fn is_secure<STATE>(req: &Request<STATE>) -> bool {
+    matches!(get_scheme(req).as_deref(), Some("https") | Some("wss"))
+}
+
+pub fn get_query_param<STATE>(req: &Request<STATE>, key: &str) -> Option<String> {
+    req.url().query_pairs().find(|(name, _)| name == key).map(|(_, value)| value.into_owned())
+}
+
+pub fn get_query_param_parse<STATE, T: std::str::FromStr>(req: &Request<STATE>, key: &str) -> HttpResult<Option<T>> {
+    match get_query_param(req, key) {
+        Some(value) => value.parse::<T>().map(Some)
+            .map_err(|_| http_err!(ErrorCode::InvalidParam, "invalid query param {}", key)),
+        None => Ok(None),
+    }
+}
+
 pub fn get_cookie<'a, STATE>(req: &'a Request<STATE>, cookie_name: &str) -> Option<String> {
     let cookie = req.header(COOKIE);
     if cookie.is_none() {
@@ -203,3 +419,231 @@ pub fn get_cookie<'a, STATE>(req: &'a Request<STATE>, cookie_name: &str) -> Opti
     None
 
 }
+
+#[cfg(test)]
+mod test {
+    use tide::http::{Method, Url};
+    use tide::http::headers::{HeaderName, HeaderValue};
+    use super::HttpServer;
+
+    #[async_std::test]
+    async fn test_global_response_header() {
+        let mut server = HttpServer::new((), "127.0.0.1".to_string(), 0, None, None);
+        server.add_global_response_header(HeaderName::from_string("x-frame-options".to_string()).unwrap(), "DENY".parse::<HeaderValue>().unwrap());
+        server.at("/test").get(|_| async { Ok("ok") });
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/test").unwrap());
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp["x-frame-options"].as_str(), "DENY");
+    }
+
+    #[async_std::test]
+    async fn test_body_string_invalid_utf8() {
+        let mut app = tide::new();
+        app.at("/text").post(|mut req: tide::Request<()>| async move {
+            let body = req.body_string().await?;
+            Ok(body)
+        });
+        let mut req = tide::http::Request::new(Method::Post, Url::parse("http://localhost/text").unwrap());
+        req.set_body(vec![b'a', 0xff, 0xfe]);
+        let resp: tide::http::Response = app.respond(req).await.unwrap();
+        assert!(resp.status().is_client_error());
+    }
+
+    #[async_std::test]
+    async fn test_compression() {
+        let mut server = HttpServer::new((), "127.0.0.1".to_string(), 0, None, None);
+        server.with(crate::tide_compression_middleware::TideCompressionMiddleware::new());
+        server.at("/data").get(|_| async {
+            Ok(tide::Response::builder(200).body("hello ".repeat(1000)).content_type(tide::http::mime::PLAIN).build())
+        });
+
+        let mut req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/data").unwrap());
+        req.insert_header("Accept-Encoding", "gzip, deflate");
+        let mut resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp["content-encoding"].as_str(), "gzip");
+        assert!(resp["vary"].as_str().contains("Accept-Encoding"));
+        assert!(resp["content-type"].as_str().starts_with("text/plain"));
+        let body = resp.body_bytes().await.unwrap();
+        assert!(body.len() < 6000);
+        let mut decoded = String::new();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(body.as_slice()), &mut decoded).unwrap();
+        assert_eq!(decoded, "hello ".repeat(1000));
+
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/data").unwrap());
+        let mut resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert!(resp.header("content-encoding").is_none());
+        assert_eq!(resp.body_string().await.unwrap(), "hello ".repeat(1000));
+    }
+
+    #[async_std::test]
+    async fn test_trailers() {
+        let mut app = tide::new();
+        app.at("/upload").post(|mut req: tide::Request<()>| async move {
+            let body = req.body_string().await?;
+            let checksum = super::get_trailers(&mut req).await
+                .and_then(|trailers| trailers.get("x-checksum").map(|v| v.as_str().to_string()))
+                .unwrap_or_default();
+            Ok(format!("{} {}", body, checksum))
+        });
+
+        let mut req = tide::http::Request::new(Method::Post, Url::parse("http://localhost/upload").unwrap());
+        req.set_body("data");
+        let sender = req.send_trailers();
+        let mut trailers = tide::http::trailers::Trailers::new();
+        trailers.insert("x-checksum", "abc");
+        sender.send(trailers).await;
+        let mut resp: tide::http::Response = app.respond(req).await.unwrap();
+        assert_eq!(resp.body_string().await.unwrap(), "data abc");
+
+        let mut req = tide::http::Request::new(Method::Post, Url::parse("http://localhost/upload").unwrap());
+        req.set_body("data");
+        let mut resp: tide::http::Response = app.respond(req).await.unwrap();
+        assert_eq!(resp.body_string().await.unwrap(), "data ");
+    }
+
+    #[async_std::test]
+    async fn test_body_json_empty() {
+        let mut app = tide::new();
+        app.at("/json").post(|mut req: tide::Request<()>| async move {
+            match super::get_body_json::<(), serde_json::Value>(&mut req).await {
+                Ok(value) => Ok(tide::Response::builder(200).body(value.to_string()).build()),
+                Err(e) => Ok(tide::Response::builder(e.status()).body(e.to_string()).build()),
+            }
+        });
+        let req = tide::http::Request::new(Method::Post, Url::parse("http://localhost/json").unwrap());
+        let mut resp: tide::http::Response = app.respond(req).await.unwrap();
+        assert_eq!(resp.status(), tide::StatusCode::BadRequest);
+        assert_eq!(resp.body_string().await.unwrap(), "empty request body, expected JSON");
+
+        let mut req = tide::http::Request::new(Method::Post, Url::parse("http://localhost/json").unwrap());
+        req.set_body(r#"{"a":x}"#);
+        let resp: tide::http::Response = app.respond(req).await.unwrap();
+        assert_eq!(resp.status(), tide::StatusCode::BadRequest);
+
+        let mut req = tide::http::Request::new(Method::Post, Url::parse("http://localhost/json").unwrap());
+        req.set_body(r#"{"a":1}"#);
+        let mut resp: tide::http::Response = app.respond(req).await.unwrap();
+        assert_eq!(resp.body_string().await.unwrap(), r#"{"a":1}"#);
+    }
+
+    #[async_std::test]
+    async fn test_expose_error_detail() {
+        let mut server = HttpServer::new((), "127.0.0.1".to_string(), 0, None, None);
+        server.at("/err").get(|_| async {
+            let ret: crate::errors::HttpResult<u32> = Err(crate::errors::http_err!(crate::errors::ErrorCode::InvalidData, "select * from user failed: /var/db locked"));
+            Ok(super::HttpJsonResult::from(ret).to_response())
+        });
+
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/err").unwrap());
+        let mut resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        let ret: super::HttpJsonResult<u32> = resp.body_json().await.unwrap();
+        assert_eq!(ret.msg, "select * from user failed: /var/db locked");
+
+        server.set_expose_error_detail(false);
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/err").unwrap());
+        let mut resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        let ret: super::HttpJsonResult<u32> = resp.body_json().await.unwrap();
+        assert_eq!(ret.err, u16::from(crate::errors::ErrorCode::InvalidData));
+        assert_eq!(ret.msg, "InvalidData");
+    }
+
+    #[async_std::test]
+    async fn test_default_cors_credentials() {
+        let mut server = HttpServer::new((), "127.0.0.1".to_string(), 0, None, None);
+        server.at("/test").get(|_| async { Ok("ok") });
+
+        let mut req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/test").unwrap());
+        req.insert_header("Origin", "http://evil.com");
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp["access-control-allow-origin"].as_str(), "*");
+        assert!(resp.header("access-control-allow-credentials").is_none());
+    }
+
+    #[async_std::test]
+    async fn test_with_cors_config() {
+        let cors = crate::cors::CorsConfig::new(vec!["http://a.com".to_string()])
+            .allow_methods("GET, PATCH")
+            .expose_headers("x-total")
+            .allow_credentials(true)
+            .max_age(600);
+        let mut server = HttpServer::with_cors_config((), "127.0.0.1".to_string(), 0, cors);
+        server.at("/test").get(|_| async { Ok("ok") });
+
+        let mut req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/test").unwrap());
+        req.insert_header("Origin", "http://a.com");
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp["access-control-allow-origin"].as_str(), "http://a.com");
+        assert_eq!(resp["access-control-allow-credentials"].as_str(), "true");
+        assert_eq!(resp["access-control-expose-headers"].as_str(), "x-total");
+
+        let mut req = tide::http::Request::new(Method::Options, Url::parse("http://localhost/test").unwrap());
+        req.insert_header("Origin", "http://a.com");
+        req.insert_header("Access-Control-Request-Method", "PATCH");
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp.status(), tide::StatusCode::NoContent);
+        assert_eq!(resp["access-control-allow-methods"].as_str(), "GET, PATCH");
+        assert_eq!(resp["access-control-max-age"].as_str(), "600");
+
+        let mut req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/test").unwrap());
+        req.insert_header("Origin", "http://b.com");
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert!(resp.header("access-control-allow-origin").is_none());
+    }
+
+    #[async_std::test]
+    async fn test_cors_expose_headers() {
+        let cors = crate::cors::CorsConfig::new(vec!["*".to_string()])
+            .allow_headers("content-type")
+            .expose_headers("x-total-count")
+            .max_age(3600);
+        let mut server = HttpServer::with_cors_config((), "127.0.0.1".to_string(), 0, cors);
+        server.at("/items").get(|_| async { Ok("[]") });
+
+        let mut req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/items").unwrap());
+        req.insert_header("Origin", "http://a.com");
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp["access-control-expose-headers"].as_str(), "x-total-count");
+
+        let mut req = tide::http::Request::new(Method::Options, Url::parse("http://localhost/items").unwrap());
+        req.insert_header("Origin", "http://a.com");
+        req.insert_header("Access-Control-Request-Method", "GET");
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp["access-control-max-age"].as_str(), "3600");
+        assert_eq!(resp["access-control-allow-headers"].as_str(), "content-type");
+
+        let mut server = HttpServer::new((), "127.0.0.1".to_string(), 0, None, Some("x-token".to_string()));
+        server.at("/items").get(|_| async { Ok("[]") });
+        let mut req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/items").unwrap());
+        req.insert_header("Origin", "http://a.com");
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp["access-control-expose-headers"].as_str(), "x-token");
+    }
+
+    #[async_std::test]
+    async fn test_serve_dir() {
+        let dir = std::env::temp_dir().join(format!("sfo_http_tide_static_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("css")).unwrap();
+        std::fs::write(dir.join("css/app.css"), "body {}").unwrap();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+
+        let mut server = HttpServer::new((), "127.0.0.1".to_string(), 0, None, None);
+        server.serve_dir("/static", &dir).unwrap();
+        server.serve_file("/", dir.join("index.html")).unwrap();
+
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/static/css/app.css").unwrap());
+        let mut resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp.status(), tide::StatusCode::Ok);
+        assert_eq!(resp.content_type().unwrap().essence(), "text/css");
+        assert_eq!(resp.body_string().await.unwrap(), "body {}");
+
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/").unwrap());
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp.content_type().unwrap().essence(), "text/html");
+
+        let req = tide::http::Request::new(Method::Get, Url::parse("http://localhost/static/missing.css").unwrap());
+        let resp: tide::http::Response = server.app.respond(req).await.unwrap();
+        assert_eq!(resp.status(), tide::StatusCode::NotFound);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use std::time::Duration;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 pub use jsonwebtoken::*;
@@ -35,6 +38,39 @@ impl<T> Payload<T> {
     }
 }
 
+pub trait RevocationStore: Send + Sync {
+    fn is_revoked(&self, jti: u64) -> bool;
+}
+
+/// In-memory denylist. A revoked `jti` is forgotten once its ttl, usually the remaining
+/// lifetime of the token, has passed.
+#[derive(Default)]
+pub struct MemoryRevocationStore {
+    revoked: Mutex<HashMap<u64, Instant>>,
+}
+
+impl MemoryRevocationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn revoke(&self, jti: u64, ttl: Duration) {
+        let now = Instant::now();
+        let mut revoked = self.revoked.lock().unwrap();
+        revoked.retain(|_, expired_at| *expired_at > now);
+        revoked.insert(jti, now + ttl);
+    }
+}
+
+impl RevocationStore for MemoryRevocationStore {
+    fn is_revoked(&self, jti: u64) -> bool {
+        match self.revoked.lock().unwrap().get(&jti) {
+            Some(expired_at) => *expired_at > Instant::now(),
+            None => false,
+        }
+    }
+}
+
 pub struct JsonWebToken;
 
 impl JsonWebToken {
@@ -69,4 +105,179 @@ impl JsonWebToken {
         let token_data: TokenData<Payload<T>> = jsonwebtoken::decode(token, key, &val)?;
         Ok(token_data.claims)
     }
+
+    /// Like `decode_payload`, but rejects tokens whose `jti` has been revoked in `store`.
+    pub fn decode_payload_with_revocation<T: for<'a> Deserialize<'a>>(token: &str, key: &DecodingKey, store: &dyn RevocationStore) -> TokenResult<Payload<T>> {
+        let payload = Self::decode_payload::<T>(token, key)?;
+        if let Some(jti) = payload.jti {
+            if store.is_revoked(jti) {
+                return Err(errors::ErrorKind::InvalidToken.into());
+            }
+        }
+        Ok(payload)
+    }
+
+    /// Reads the payload without checking the signature or expiry. Never use it for authentication,
+    /// only to inspect untrusted tokens, e.g. to pick the verification key or log the subject.
+    pub fn decode_unverified<T: for<'a> Deserialize<'a>>(token: &str) -> TokenResult<Payload<T>> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let mut val = Validation::new(header.alg);
+        val.insecure_disable_signature_validation();
+        val.validate_exp = false;
+        val.validate_aud = false;
+        val.required_spec_claims.clear();
+        let token_data: TokenData<Payload<T>> = jsonwebtoken::decode(token, &DecodingKey::from_secret(&[]), &val)?;
+        Ok(token_data.claims)
+    }
+}
+
+const ACCESS_SUBJECT: &str = "access";
+const REFRESH_SUBJECT: &str = "refresh";
+
+fn next_jti() -> u64 {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let now = Utc::now().timestamp_micros() as u64;
+    now.wrapping_shl(12) | (SEQ.fetch_add(1, Ordering::Relaxed) & 0xfff)
+}
+
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// Issues a short-lived access token together with a long-lived refresh token carrying the
+/// same data. The two are told apart by `sub`, so a refresh token is never accepted as an
+/// access token by `decode_access` and vice versa.
+pub struct RefreshTokenIssuer {
+    alg: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    access_ttl: Duration,
+    refresh_ttl: Duration,
+}
+
+impl RefreshTokenIssuer {
+    pub fn new(alg: Algorithm, encoding_key: EncodingKey, decoding_key: DecodingKey) -> Self {
+        Self {
+            alg,
+            encoding_key,
+            decoding_key,
+            access_ttl: Duration::from_secs(15 * 60),
+            refresh_ttl: Duration::from_secs(30 * 24 * 3600),
+        }
+    }
+
+    pub fn set_access_ttl(mut self, ttl: Duration) -> Self {
+        self.access_ttl = ttl;
+        self
+    }
+
+    pub fn set_refresh_ttl(mut self, ttl: Duration) -> Self {
+        self.refresh_ttl = ttl;
+        self
+    }
+
+    fn issue_token<T: Serialize>(&self, subject: &str, data: &T, ttl: Duration) -> TokenResult<String> {
+        let now = Utc::now().timestamp() as u64;
+        let payload = Payload {
+            iss: None,
+            exp: Some(now + ttl.as_secs()),
+            sub: Some(subject.to_string()),
+            aud: None,
+            nbf: None,
+            iat: Some(now),
+            jti: Some(next_jti()),
+            data,
+        };
+        jsonwebtoken::encode(&Header::new(self.alg), &payload, &self.encoding_key)
+    }
+
+    fn decode_token<T: for<'a> Deserialize<'a>>(&self, subject: &str, token: &str) -> TokenResult<Payload<T>> {
+        let mut val = Validation::new(self.alg);
+        val.validate_exp = true;
+        val.leeway = 0;
+        val.sub = Some(subject.to_string());
+        let token_data: TokenData<Payload<T>> = jsonwebtoken::decode(token, &self.decoding_key, &val)?;
+        Ok(token_data.claims)
+    }
+
+    pub fn issue<T: Serialize>(&self, data: &T) -> TokenResult<TokenPair> {
+        Ok(TokenPair {
+            access_token: self.issue_token(ACCESS_SUBJECT, data, self.access_ttl)?,
+            refresh_token: self.issue_token(REFRESH_SUBJECT, data, self.refresh_ttl)?,
+        })
+    }
+
+    /// Validates `refresh_token` and issues a new access token for its data.
+    pub fn refresh<T: Serialize + for<'a> Deserialize<'a>>(&self, refresh_token: &str) -> TokenResult<String> {
+        let payload = self.decode_token::<T>(REFRESH_SUBJECT, refresh_token)?;
+        self.issue_token(ACCESS_SUBJECT, &payload.data, self.access_ttl)
+    }
+
+    pub fn decode_access<T: for<'a> Deserialize<'a>>(&self, access_token: &str) -> TokenResult<Payload<T>> {
+        self.decode_token(ACCESS_SUBJECT, access_token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use super::*;
+
+    #[test]
+    fn test_decode_unverified() {
+        let expired_at = Utc::now() - chrono::Duration::hours(1);
+        let token = JsonWebToken::encode(Algorithm::HS256, "alice".to_string(), expired_at, &EncodingKey::from_secret(b"secret")).unwrap();
+        assert!(JsonWebToken::decode::<String>(token.as_str(), &DecodingKey::from_secret(b"secret")).is_err());
+
+        let payload = JsonWebToken::decode_unverified::<String>(token.as_str()).unwrap();
+        assert_eq!(payload.data, "alice");
+        assert_eq!(payload.exp, Some(expired_at.timestamp() as u64));
+    }
+
+    #[test]
+    fn test_revoked_token() {
+        let payload = Payload {
+            iss: None,
+            exp: Some((Utc::now() + chrono::Duration::hours(1)).timestamp() as u64),
+            sub: None,
+            aud: None,
+            nbf: None,
+            iat: None,
+            jti: Some(7),
+            data: "alice".to_string(),
+        };
+        let token = jsonwebtoken::encode(&Header::new(Algorithm::HS256), &payload, &EncodingKey::from_secret(b"secret")).unwrap();
+        let key = DecodingKey::from_secret(b"secret");
+
+        let store = MemoryRevocationStore::new();
+        assert!(JsonWebToken::decode_payload_with_revocation::<String>(token.as_str(), &key, &store).is_ok());
+
+        store.revoke(7, Duration::from_secs(3600));
+        let err = JsonWebToken::decode_payload_with_revocation::<String>(token.as_str(), &key, &store).err().unwrap();
+        assert_eq!(err.kind(), &errors::ErrorKind::InvalidToken);
+
+        store.revoke(7, Duration::ZERO);
+        assert!(JsonWebToken::decode_payload_with_revocation::<String>(token.as_str(), &key, &store).is_ok());
+    }
+
+    #[test]
+    fn test_refresh_token() {
+        let issuer = RefreshTokenIssuer::new(Algorithm::HS256, EncodingKey::from_secret(b"secret"), DecodingKey::from_secret(b"secret"));
+        let pair = issuer.issue(&"alice".to_string()).unwrap();
+        assert_eq!(issuer.decode_access::<String>(pair.access_token.as_str()).unwrap().data, "alice");
+        assert!(issuer.decode_access::<String>(pair.refresh_token.as_str()).is_err());
+        assert!(issuer.refresh::<String>(pair.access_token.as_str()).is_err());
+
+        let access_token = issuer.refresh::<String>(pair.refresh_token.as_str()).unwrap();
+        let payload = issuer.decode_access::<String>(access_token.as_str()).unwrap();
+        assert_eq!(payload.data, "alice");
+        assert!(payload.iat.is_some());
+        assert!(payload.jti.is_some());
+
+        let issuer = issuer.set_refresh_ttl(Duration::ZERO);
+        let pair = issuer.issue(&"alice".to_string()).unwrap();
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(issuer.refresh::<String>(pair.refresh_token.as_str()).is_err());
+    }
 }